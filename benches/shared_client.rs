@@ -0,0 +1,114 @@
+// Latency per message with a new client for every request, as before, and with the shared one.
+// Run with `cargo bench --bench shared_client`.
+// The local server needs no TLS, against Gemini the shared client also saves a TLS handshake
+// per request.
+#[path = "../src/app/http.rs"]
+mod http;
+
+use http::HttpSettings;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+const MESSAGES: u32 = 200;
+const REQUEST: &str = r#"{"contents": [{"role": "user", "parts": [{"text": "ping"}]}]}"#;
+const PONG: &str =
+    r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#;
+
+#[tokio::main]
+async fn main() {
+    let url = format!("{}/v1beta/models/flash:generateContent", serve().await);
+
+    let fresh = per_message(|| async {
+        ping(&reqwest::Client::new(), &url).await;
+    })
+    .await;
+
+    let client = HttpSettings::default().build_client().unwrap();
+    let shared = per_message(|| ping(&client, &url)).await;
+
+    let construction = per_message(|| async {
+        drop(reqwest::Client::new());
+    })
+    .await;
+
+    println!(
+        "per message: new client {:?}, shared client {:?}, saved {:?} (client construction alone {:?})",
+        fresh,
+        shared,
+        fresh.saturating_sub(shared),
+        construction
+    );
+}
+
+async fn per_message<F, Fut>(mut run: F) -> Duration
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let started = Instant::now();
+    for _ in 0..MESSAGES {
+        run().await;
+    }
+    started.elapsed() / MESSAGES
+}
+
+async fn ping(client: &reqwest::Client, url: &str) {
+    client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(REQUEST)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+}
+
+/// Answers every request with `PONG` and keeps connections open, returns the base URL.
+async fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                loop {
+                    let mut length = 0;
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                length = value.trim().parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    if stream.read_exact(&mut body).await.is_err() {
+                        return;
+                    }
+
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        PONG.len(),
+                        PONG
+                    );
+                    if stream.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    url
+}
@@ -0,0 +1,6 @@
+-- Up
+CREATE TABLE IF NOT EXISTS user_settings (
+    id INTEGER PRIMARY KEY,
+    user_id INT NOT NULL UNIQUE,
+    model TEXT NULL
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Up
+ALTER TABLE messages ADD COLUMN attachments TEXT NULL;
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Up
+CREATE TABLE IF NOT EXISTS gemini_files (
+    id INTEGER PRIMARY KEY,
+    file_unique_id TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    file_uri TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    expires_at INT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Up
+ALTER TABLE user_settings ADD COLUMN grounding INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
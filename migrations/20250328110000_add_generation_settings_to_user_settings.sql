@@ -0,0 +1,8 @@
+-- Up
+ALTER TABLE user_settings ADD COLUMN preset TEXT NULL;
+ALTER TABLE user_settings ADD COLUMN temperature REAL NULL;
+ALTER TABLE user_settings ADD COLUMN top_p REAL NULL;
+ALTER TABLE user_settings ADD COLUMN top_k INTEGER NULL;
+ALTER TABLE user_settings ADD COLUMN max_output_tokens INTEGER NULL;
+ALTER TABLE user_settings ADD COLUMN stop_sequences TEXT NULL;
+ALTER TABLE user_settings ADD COLUMN thinking_budget INTEGER NULL;
\ No newline at end of file
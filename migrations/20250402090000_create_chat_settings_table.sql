@@ -0,0 +1,6 @@
+-- Up
+CREATE TABLE IF NOT EXISTS chat_settings (
+    id INTEGER PRIMARY KEY,
+    chat_id INT NOT NULL UNIQUE,
+    safety_settings TEXT NOT NULL DEFAULT '[]'
+);
\ No newline at end of file
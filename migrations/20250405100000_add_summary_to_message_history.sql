@@ -0,0 +1,2 @@
+-- Up
+ALTER TABLE message_history ADD COLUMN summary TEXT NULL;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Up
+CREATE TABLE IF NOT EXISTS gemini_caches (
+    id INTEGER PRIMARY KEY,
+    user_id INT NOT NULL UNIQUE,
+    name TEXT NULL,
+    model TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    prefix_len INT NOT NULL,
+    expires_at INT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
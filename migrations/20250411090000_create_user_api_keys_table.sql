@@ -0,0 +1,7 @@
+-- Up
+CREATE TABLE IF NOT EXISTS user_api_keys (
+    id INTEGER PRIMARY KEY,
+    user_id INT NOT NULL UNIQUE,
+    encrypted_key TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Up
+ALTER TABLE user_settings ADD COLUMN backend TEXT NULL;
\ No newline at end of file
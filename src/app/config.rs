@@ -1,22 +1,269 @@
 use std::env;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
+use crate::app::http::{
+    self, HttpSettings, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_READ_TIMEOUT_SECS,
+    DEFAULT_REQUEST_TIMEOUT_SECS,
+};
 use crate::db::database::Database;
+use crate::gemini::backend::BackendKind;
+use crate::gemini::keys::{KeyPool, KeySelection};
+use crate::gemini::retry::RetryPolicy;
+use crate::gemini::safety;
+use crate::gemini::services::GenerateOptions;
+use crate::gemini::vertex::{ServiceAccount, Vertex, DEFAULT_VERTEX_LOCATION};
+use crate::openai::OpenAiSettings;
+use crate::tools::ToolRegistry;
+use crate::utils::crypto::MasterKey;
+use crate::utils::redact;
+
+pub const DEFAULT_GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+pub const DEFAULT_GEMINI_MODELS: &str = "gemini-2.0-flash,gemini-2.0-flash-lite";
+pub const DEFAULT_KEY_COOLDOWN_SECS: u64 = 60;
+pub const DEFAULT_HISTORY_TOKEN_BUDGET: i64 = 32_000;
+pub const DEFAULT_CACHE_TTL_SECS: i64 = 60 * 60;
+pub const DEFAULT_CACHE_MIN_TOKENS: i64 = 4096;
+pub const DEFAULT_PRIVATE_SAFETY_LIMIT: &str = "BLOCK_ONLY_HIGH";
 
 // Configuration module
 // #[derive(Clone)]
 pub struct AppConfig {
-    pub gemini_api_key: String,
+    /// API keys requests are spread over, the first one also owns uploaded files and caches.
+    pub gemini_keys: KeyPool,
+    pub gemini_base_url: String,
+    /// Sends Gemini requests to Vertex AI instead of AI Studio, the API keys are not used then.
+    pub vertex: Option<Vertex>,
+    /// Shared by every request to Gemini, so connections are reused.
+    pub http_client: reqwest::Client,
+    /// Models to ask in order, the ones after the first are only used when the previous one keeps failing.
+    pub gemini_models: Vec<String>,
+    /// Models users can pick with /model.
+    pub selectable_models: Vec<String>,
+    pub retry_policy: RetryPolicy,
+    /// OpenAI-compatible server users can switch to with /backend, unset when not configured.
+    pub openai: Option<OpenAiSettings>,
+    /// Backend of users who didn't pick one.
+    pub default_backend: BackendKind,
+    /// Tokens the replayed history may take, older turns are summarized beyond it.
+    pub history_token_budget: i64,
+    /// Encrypts the API keys users register with /setkey, the command is disabled without it.
+    pub master_key: Option<MasterKey>,
+    /// Lifetime of context caches, refreshed while the conversation goes on. 0 turns caching off.
+    pub cache_ttl_secs: i64,
+    /// Estimated tokens a prefix without documents needs before it is cached.
+    pub cache_min_tokens: i64,
+    /// Loosest block threshold users may pick in their private chat, group admins are not limited.
+    pub private_safety_limit: String,
+    /// Whether debug logs may contain the text of messages and responses, otherwise only their length.
+    pub log_content: bool,
+    /// Functions the model can call while answering chat messages.
+    pub tools: ToolRegistry,
     pub database: Mutex<Database>,
 }
 
 impl AppConfig {
     pub fn new(database: Database) -> Self {
-        let gemini_api_key =
-            env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY was not found in env");
+        // Falling back to AI Studio could send requests where they are not allowed to go
+        let vertex = env::var("VERTEX_CREDENTIALS").ok().map(|path| {
+            let account = std::fs::read_to_string(path.trim())
+                .map_err(|err| err.to_string())
+                .and_then(|json| ServiceAccount::from_json(&json))
+                .and_then(|account| {
+                    Vertex::new(
+                        account,
+                        env::var("VERTEX_PROJECT").ok(),
+                        &env::var("VERTEX_LOCATION")
+                            .unwrap_or_else(|_| String::from(DEFAULT_VERTEX_LOCATION)),
+                    )
+                });
+            account.unwrap_or_else(|err| panic!("Invalid Vertex AI settings: {}", err))
+        });
+
+        let mut gemini_api_keys = parse_list(&env::var("GEMINI_API_KEYS").unwrap_or_default());
+        if gemini_api_keys.is_empty() {
+            match env::var("GEMINI_API_KEY") {
+                Ok(key) => gemini_api_keys.push(key),
+                Err(_) if vertex.is_some() => {}
+                Err(_) => panic!("GEMINI_API_KEY was not found in env"),
+            }
+        }
+        let gemini_keys = KeyPool::new(
+            gemini_api_keys,
+            env_or("GEMINI_KEY_SELECTION", KeySelection::RoundRobin),
+            Duration::from_secs(env_or(
+                "GEMINI_KEY_COOLDOWN_SECS",
+                DEFAULT_KEY_COOLDOWN_SECS,
+            )),
+        );
+        let mut gemini_models = parse_list(&env::var("GEMINI_MODELS").unwrap_or_default());
+        if gemini_models.is_empty() {
+            gemini_models = parse_list(DEFAULT_GEMINI_MODELS);
+        }
+        let mut selectable_models =
+            parse_list(&env::var("GEMINI_SELECTABLE_MODELS").unwrap_or_default());
+        if selectable_models.is_empty() {
+            selectable_models = gemini_models.clone();
+        }
+
+        let default_policy = RetryPolicy::default();
+        let retry_policy = RetryPolicy {
+            max_retries: env_or("GEMINI_MAX_RETRIES", default_policy.max_retries),
+            base_delay: Duration::from_millis(env_or(
+                "GEMINI_RETRY_BASE_DELAY_MS",
+                default_policy.base_delay.as_millis() as u64,
+            )),
+            max_delay: Duration::from_millis(env_or(
+                "GEMINI_RETRY_MAX_DELAY_MS",
+                default_policy.max_delay.as_millis() as u64,
+            )),
+        };
+
+        let private_safety_limit = match env::var("GEMINI_PRIVATE_SAFETY_LIMIT") {
+            Ok(value) => safety::parse_threshold(&value).unwrap_or_else(|| {
+                log::warn!("Invalid value for GEMINI_PRIVATE_SAFETY_LIMIT, using the default");
+                DEFAULT_PRIVATE_SAFETY_LIMIT
+            }),
+            Err(_) => DEFAULT_PRIVATE_SAFETY_LIMIT,
+        };
+
+        let master_key = env::var("BYOK_MASTER_KEY").ok().and_then(|value| {
+            redact::register_secret(value.trim());
+            MasterKey::from_base64(&value)
+                .map_err(|err| log::error!("BYOK_MASTER_KEY is ignored: {}", err))
+                .ok()
+        });
+
+        let openai = match (env::var("OPENAI_BASE_URL"), env::var("OPENAI_MODEL")) {
+            (Ok(base_url), Ok(model)) => {
+                let api_key = env::var("OPENAI_API_KEY")
+                    .ok()
+                    .filter(|x| !x.trim().is_empty());
+                if let Some(api_key) = &api_key {
+                    redact::register_secret(api_key.trim());
+                }
+                Some(OpenAiSettings {
+                    base_url: base_url.trim().to_string(),
+                    api_key: api_key.map(|x| x.trim().to_string()),
+                    model: model.trim().to_string(),
+                })
+            }
+            _ => None,
+        };
+        let mut default_backend = env_or("BACKEND", BackendKind::Gemini);
+        if default_backend == BackendKind::OpenAi && openai.is_none() {
+            log::warn!(
+                "BACKEND is openai but OPENAI_BASE_URL or OPENAI_MODEL is missing, using gemini"
+            );
+            default_backend = BackendKind::Gemini;
+        }
+
+        let http_settings = HttpSettings {
+            connect_timeout: Duration::from_secs(env_or(
+                "GEMINI_CONNECT_TIMEOUT_SECS",
+                DEFAULT_CONNECT_TIMEOUT_SECS,
+            )),
+            read_timeout: Duration::from_secs(env_or(
+                "GEMINI_READ_TIMEOUT_SECS",
+                DEFAULT_READ_TIMEOUT_SECS,
+            )),
+            request_timeout: Duration::from_secs(env_or(
+                "GEMINI_REQUEST_TIMEOUT_SECS",
+                DEFAULT_REQUEST_TIMEOUT_SECS,
+            )),
+            proxy: env::var("GEMINI_PROXY")
+                .ok()
+                .filter(|x| !x.trim().is_empty()),
+            user_agent: env::var("GEMINI_USER_AGENT")
+                .unwrap_or_else(|_| http::default_user_agent()),
+        };
+        // A broken proxy setting must not silently send requests around the proxy
+        let http_client = http_settings
+            .build_client()
+            .unwrap_or_else(|err| panic!("Invalid HTTP client settings: {}", err));
+
         Self {
-            gemini_api_key,
+            gemini_keys,
+            gemini_base_url: env::var("GEMINI_BASE_URL")
+                .ok()
+                .map(|x| x.trim().trim_end_matches('/').to_string())
+                .filter(|x| !x.is_empty())
+                .unwrap_or_else(|| String::from(DEFAULT_GEMINI_BASE_URL)),
+            vertex,
+            http_client,
+            gemini_models,
+            selectable_models,
+            retry_policy,
+            openai,
+            default_backend,
+            history_token_budget: env_or(
+                "GEMINI_HISTORY_TOKEN_BUDGET",
+                DEFAULT_HISTORY_TOKEN_BUDGET,
+            ),
+            master_key,
+            cache_ttl_secs: env_or("GEMINI_CACHE_TTL_SECS", DEFAULT_CACHE_TTL_SECS),
+            cache_min_tokens: env_or("GEMINI_CACHE_MIN_TOKENS", DEFAULT_CACHE_MIN_TOKENS),
+            private_safety_limit: String::from(private_safety_limit),
+            log_content: env_or("LOG_MESSAGE_CONTENT", false),
+            tools: ToolRegistry::with_builtin_tools(),
             database: Mutex::new(database),
         }
     }
+
+    /// Models to try for a request, starting with the one the user picked.
+    pub fn model_chain(&self, preferred: Option<&str>) -> Vec<String> {
+        let mut chain: Vec<String> = preferred
+            .filter(|x| self.selectable_models.iter().any(|model| model == x))
+            .map(String::from)
+            .into_iter()
+            .collect();
+        for model in &self.gemini_models {
+            if !chain.contains(model) {
+                chain.push(model.clone());
+            }
+        }
+        chain
+    }
+
+    /// Whether attachments of the request may be uploaded through the Files API, only AI Studio
+    /// can read them. Uploads belong to the project of the primary key, so attachments of users
+    /// with their own key are sent inline and their requests never need the bot's keys.
+    pub fn uses_files_api(&self, options: &GenerateOptions) -> bool {
+        self.backend(options.backend) == BackendKind::Gemini
+            && self.vertex.is_none()
+            && options.api_key.is_none()
+    }
+
+    /// Whether conversations may be kept in context caches. They are an AI Studio feature stored
+    /// in the project of the primary key, requests to Vertex AI are always sent in full.
+    pub fn uses_context_cache(&self) -> bool {
+        self.cache_ttl_secs > 0 && self.vertex.is_none()
+    }
+
+    /// The backend answering a request, the user's pick only counts when it is configured.
+    pub fn backend(&self, preferred: Option<BackendKind>) -> BackendKind {
+        match preferred {
+            Some(BackendKind::OpenAi) if self.openai.is_some() => BackendKind::OpenAi,
+            Some(BackendKind::Gemini) => BackendKind::Gemini,
+            _ => self.default_backend,
+        }
+    }
+}
+
+/// Splits a comma separated list, skipping empty items.
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Invalid value for {}, using the default", name);
+            default
+        }),
+        Err(_) => default,
+    }
 }
@@ -0,0 +1,62 @@
+// The HTTP client shared by every request to Gemini
+use std::time::Duration;
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+/// Interval of HTTP/2 pings keeping idle connections to Gemini open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long unused connections stay in the pool.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Timeouts, proxy and user agent of the shared client.
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// Time to establish a connection, including the TLS handshake.
+    pub connect_timeout: Duration,
+    /// Longest wait for the next bytes of a response, ends stuck streams.
+    pub read_timeout: Duration,
+    /// Limit for a whole request, from sending it until the last byte of the response.
+    pub request_timeout: Duration,
+    /// Proxy for every request, e.g. `http://proxy:8080`.
+    /// The usual `HTTPS_PROXY` variables apply when unset.
+    pub proxy: Option<String>,
+    pub user_agent: String,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS),
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            proxy: None,
+            user_agent: default_user_agent(),
+        }
+    }
+}
+
+impl HttpSettings {
+    /// Builds the client, connections are pooled and kept alive between requests.
+    pub fn build_client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .timeout(self.request_timeout)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(KEEP_ALIVE_INTERVAL)
+            .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+            .http2_keep_alive_timeout(KEEP_ALIVE_TIMEOUT)
+            .http2_keep_alive_while_idle(true)
+            .user_agent(&self.user_agent);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        builder.build()
+    }
+}
+
+pub fn default_user_agent() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
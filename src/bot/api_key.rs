@@ -0,0 +1,107 @@
+// API keys users bring with /setkey, stored encrypted with the master key
+use crate::app::config::AppConfig;
+use crate::gemini::error::GeminiError;
+use crate::gemini::keys;
+use crate::models::user_api_key::UserApiKey;
+use crate::utils::redact;
+use std::sync::Arc;
+
+const DISABLED: &str = "Using your own API key is not enabled on this bot.";
+
+/// Handles `/setkey <key>`: checks the key with Gemini, then stores it encrypted for the user.
+/// Returns the answer for the user or what was wrong.
+pub async fn set_key(config: &Arc<AppConfig>, user_id: i64, key: &str) -> Result<String, String> {
+    let master_key = config.master_key.as_ref().ok_or(DISABLED)?;
+    if config.vertex.is_some() {
+        return Err(String::from(DISABLED));
+    }
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err(String::from(
+            "Send your Gemini API key with the command, e.g. /setkey AIza...",
+        ));
+    }
+
+    match keys::validate_key(config, key).await {
+        Ok(()) => {}
+        Err(GeminiError::HttpStatus {
+            status: 400 | 401 | 403,
+            ..
+        }) => {
+            return Err(String::from(
+                "Gemini did not accept this key, check that it is a valid API key.",
+            ));
+        }
+        Err(err) => {
+            log::warn!("Could not validate an API key of {}: {}", user_id, err);
+            return Err(String::from(
+                "Could not check the key right now, please try again later.",
+            ));
+        }
+    }
+
+    redact::register_secret(key);
+    let stored = UserApiKey::new(user_id, master_key.encrypt(key, &context(user_id)));
+    let db = config.database.lock().await;
+    match stored.insert(&db).await {
+        Ok(()) => Ok(format!(
+            "Your key {} is saved, your requests now use it. Use /removekey to go back to the bot's keys.",
+            keys::label(key)
+        )),
+        Err(err) => {
+            log::error!("Failed to save API key: {}", err);
+            Err(String::from("Something didnt go well, please try again later."))
+        }
+    }
+}
+
+/// Handles `/removekey`, returns the answer for the user.
+pub async fn remove_key(config: &Arc<AppConfig>, user_id: i64) -> String {
+    let db = config.database.lock().await;
+    match UserApiKey::find_by_user_id(user_id, &db).await {
+        Ok(None) => String::from("You have no API key saved."),
+        Ok(Some(_)) => match UserApiKey::delete_by_user_id(user_id, &db).await {
+            Ok(()) => {
+                String::from("Your API key is removed, your requests use the bot's keys again.")
+            }
+            Err(err) => {
+                log::error!("Failed to delete API key: {}", err);
+                String::from("Something didnt go well, please try again later.")
+            }
+        },
+        Err(err) => {
+            log::error!("Failed to load API key: {}", err);
+            String::from("Something didnt go well, please try again later.")
+        }
+    }
+}
+
+/// The decrypted key of the user, None when they have none or it can't be decrypted anymore,
+/// e.g. because the master key changed. Always None with Vertex AI, which only takes the service account.
+pub async fn user_key(config: &Arc<AppConfig>, user_id: i64) -> Option<String> {
+    let master_key = config.master_key.as_ref()?;
+    if config.vertex.is_some() {
+        return None;
+    }
+    let stored = {
+        let db = config.database.lock().await;
+        UserApiKey::find_by_user_id(user_id, &db)
+            .await
+            .unwrap_or_else(|err| {
+                log::error!("Error while loading API key: {:?}", err);
+                None
+            })?
+    };
+
+    let key = master_key.decrypt(&stored.encrypted_key, &context(user_id));
+    match &key {
+        Some(key) => redact::register_secret(key),
+        None => log::warn!("The API key of {} can't be decrypted, ignoring it", user_id),
+    }
+    key
+}
+
+/// Binds a stored key to its owner, so it can't be copied to another user's row.
+fn context(user_id: i64) -> [u8; 8] {
+    user_id.to_be_bytes()
+}
@@ -1,7 +1,15 @@
 // Bot logic module
-use crate::gemini::services::{escape_markdown, query_gemini_api};
+use crate::bot::{api_key, media, safety, settings};
+use crate::gemini::backend::{Backend, BackendKind, Prompt};
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{escape_markdown, GeminiReply, GenerateOptions, HistoryTurn};
+use crate::gemini::{cache, history};
+use crate::models::chat_settings::ChatSettings;
+use crate::models::message::Attachment;
 use crate::models::message_history::MessageHistory;
 use crate::models::user::User;
+use crate::models::user_settings::UserSettings;
+use crate::utils::redact;
 use crate::utils::time::unix_timestamp;
 use crate::{utils, AppConfig};
 use std::collections::HashMap;
@@ -10,18 +18,26 @@ use teloxide::dispatching::dialogue::GetChatId;
 use teloxide::dispatching::DefaultKey;
 use teloxide::prelude::*;
 use teloxide::types::{
-    InputFile, InputMessageContent, InputMessageContentText, ReplyParameters, Update, UpdateKind,
+    InlineKeyboardButton, InlineKeyboardMarkup, InputFile, InputMessageContent,
+    InputMessageContentText, ReplyParameters, Update, UpdateKind,
 };
 use teloxide::utils::command::BotCommands;
 use teloxide::utils::html;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Minimum delay between two edits of the placeholder while a response is streamed,
+/// keeps us below the rate limits telegram applies to `editMessageText`.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+/// Inline answers have to fit in one article, roughly 4 characters per token.
+const INLINE_MAX_OUTPUT_TOKENS: u32 = 1000;
 
 pub type UserStates = Arc<Mutex<HashMap<i64, UserState>>>;
 
-// #[derive(Clone)]
+/// The inline query a user is typing, answered once they stop for a moment.
 pub struct UserState {
     query: String,
     task: Option<tokio::task::JoinHandle<()>>,
@@ -45,18 +61,44 @@ enum Command {
     DeveloperInfo,
     #[command(description = "create a new topic")]
     NewTopic,
+    #[command(description = "choose the Gemini model used for your requests")]
+    Model,
+    #[command(description = "reply to a voice message to get its transcript")]
+    Transcribe,
+    #[command(description = "set your timezone, e.g. /timezone Europe/Berlin")]
+    Timezone(String),
+    #[command(description = "turn Google Search grounding of answers on or off")]
+    Grounding,
+    #[command(description = "show or change generation settings, e.g. /settings temperature 0.5")]
+    Settings(String),
+    #[command(
+        description = "show or change the safety filters of this chat, e.g. /safety harassment high"
+    )]
+    Safety(String),
+    #[command(
+        description = "use your own Gemini API key for your requests, send it in a private chat"
+    )]
+    SetKey(String),
+    #[command(description = "remove your own API key and go back to the bot's keys")]
+    RemoveKey,
+    #[command(description = "show or switch the backend answering you, e.g. /backend openai")]
+    Backend(String),
 }
 
+const TRANSCRIBE_PROMPT: &str =
+    "Transcribe this audio word by word in its original language. Reply with the transcript only.";
+
+/// Prefix of the callback data sent by the /model keyboard.
+const MODEL_CALLBACK_PREFIX: &str = "model:";
+
 pub async fn setup_dispatcher(
     bot: Bot,
     config: Arc<AppConfig>,
+    backend: Backend,
     user_states: UserStates,
 ) -> Dispatcher<Bot, teloxide::RequestError, DefaultKey> {
     let handler = dptree::entry()
-        .inspect(|u: Update| {
-            #[cfg(debug_assertions)]
-            log::debug!("Incoming request: {:?}", &u);
-        })
+        .inspect(log_update)
         .inspect_async(before_handling)
         .branch(
             Update::filter_message()
@@ -66,15 +108,36 @@ pub async fn setup_dispatcher(
                         .endpoint(command_handler),
                 )
                 .endpoint(message_handler),
-        );
-        //.branch(Update::filter_inline_query().branch(dptree::entry().endpoint(inline_handler))); // disabled, probebly causing stack overflow
+        )
+        .branch(Update::filter_callback_query().endpoint(callback_handler))
+        .branch(Update::filter_inline_query().endpoint(inline_handler));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![config, user_states])
+        .dependencies(dptree::deps![config, backend, user_states])
         .enable_ctrlc_handler()
         .build()
 }
 
+/// One line per update, with the text only when the operator allowed message content in logs.
+fn log_update(update: Update, config: Arc<AppConfig>) {
+    let text = match &update.kind {
+        UpdateKind::Message(msg) => msg.text().or(msg.caption()),
+        UpdateKind::CallbackQuery(query) => query.data.as_deref(),
+        UpdateKind::InlineQuery(query) => Some(query.query.as_str()),
+        _ => None,
+    };
+    log::debug!(
+        "Update {}: chat {:?}, from {:?}, text {}",
+        update.id.0,
+        update.chat_id().map(|x| x.0),
+        update.from().map(|x| x.id.0),
+        text.map_or(String::from("none"), |x| redact::content(
+            x,
+            config.log_content
+        ))
+    );
+}
+
 async fn before_handling(_bot: Bot, update: Update, config: Arc<AppConfig>) {
     if let UpdateKind::Message(_msg) = &update.kind {
         let chat_id = update.chat_id().expect("Could not retrive chat id!");
@@ -85,25 +148,66 @@ async fn before_handling(_bot: Bot, update: Update, config: Arc<AppConfig>) {
 
         let sender_id = update.from().expect("Could not retrive sender id!").id.0 as i64;
         let db = config.database.lock().await;
-        let user = match User::find_by_id(sender_id, &db).await.unwrap() {
-            Some(user) => user,
-            None => {
-                let user = User::new(
-                    chat_id.0,
-                    chat.username().map(String::from),
-                    chat.first_name().map(String::from),
-                    chat.last_name().map(String::from),
-                );
-                user.insert(&db).await.unwrap();
-                user
-            }
-        };
+        if User::find_by_id(sender_id, &db).await.unwrap().is_none() {
+            let user = User::new(
+                chat_id.0,
+                chat.username().map(String::from),
+                chat.first_name().map(String::from),
+                chat.last_name().map(String::from),
+            );
+            user.insert(&db).await.unwrap();
+        }
     }
 }
 
-async fn message_handler(bot: Bot, msg: Message, config: Arc<AppConfig>) -> ResponseResult<()> {
-    if let Some(text) = msg.text().clone() {
-        generate_response(bot, &msg, text.to_string(), config).await?;
+async fn message_handler(
+    bot: Bot,
+    msg: Message,
+    config: Arc<AppConfig>,
+    backend: Backend,
+) -> ResponseResult<()> {
+    if let Some(text) = msg.text() {
+        generate_response(bot, &msg, text.to_string(), Vec::new(), config, &backend).await?;
+    } else if let Some(photo) = media::photo_attachment(&msg) {
+        let text = msg.caption().unwrap_or("Describe this image.").to_string();
+        generate_response(bot, &msg, text, vec![photo], config, &backend).await?;
+    } else if let Some(audio) = media::audio_attachment(&msg) {
+        let text = msg
+            .caption()
+            .unwrap_or("Listen to this audio and reply to what is said in it.")
+            .to_string();
+        generate_response(bot, &msg, text, vec![audio], config, &backend).await?;
+    } else if let Some(document) = msg.document() {
+        if document.file.size > media::TELEGRAM_DOWNLOAD_LIMIT {
+            bot.send_message(
+                msg.chat.id,
+                "This file is too large, files up to 20MB are supported.",
+            )
+            .reply_parameters(ReplyParameters::new(msg.id))
+            .await?;
+        } else if let Some(attachment) = media::document_attachment(&msg) {
+            match msg.caption() {
+                Some(caption) => {
+                    generate_response(
+                        bot,
+                        &msg,
+                        caption.to_string(),
+                        vec![attachment],
+                        config,
+                        &backend,
+                    )
+                    .await?
+                }
+                None => ask_about_document(bot, &msg, attachment, config).await?,
+            }
+        } else {
+            bot.send_message(
+                msg.chat.id,
+                "I can only read PDFs, images, audio, text and source code files.",
+            )
+            .reply_parameters(ReplyParameters::new(msg.id))
+            .await?;
+        }
     } else {
         bot.send_message(msg.chat.id, Command::descriptions().to_string())
             .await?;
@@ -117,6 +221,7 @@ async fn command_handler(
     msg: Message,
     cmd: Command,
     config: Arc<AppConfig>,
+    backend: Backend,
 ) -> ResponseResult<()> {
     match cmd {
         Command::Start | Command::Help => {
@@ -124,7 +229,65 @@ async fn command_handler(
                 .await?;
         }
         Command::Generate(text) => {
-            generate_response(bot, &msg, text, config).await?;
+            generate_response(bot, &msg, text, Vec::new(), config, &backend).await?;
+        }
+        Command::Model => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            let current = current_model(sender_id, &config).await;
+            bot.send_message(
+                msg.chat.id,
+                format!("You are currently using {}, pick a model:", current),
+            )
+            .reply_markup(model_keyboard(&config.selectable_models, &current))
+            .await?;
+        }
+        Command::Transcribe => match msg.reply_to_message().and_then(media::audio_attachment) {
+            Some(audio) => transcribe(&bot, &msg, &audio, &config, &backend).await?,
+            None => {
+                bot.send_message(
+                    msg.chat.id,
+                    "Use /transcribe as a reply to a voice message.",
+                )
+                .reply_parameters(ReplyParameters::new(msg.id))
+                .await?;
+            }
+        },
+        Command::Timezone(timezone) => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            let reply = set_timezone(sender_id, timezone.trim(), &config).await;
+            bot.send_message(msg.chat.id, reply)
+                .reply_parameters(ReplyParameters::new(msg.id))
+                .await?;
+        }
+        Command::Grounding => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            let reply = toggle_grounding(sender_id, &config).await;
+            bot.send_message(msg.chat.id, reply)
+                .reply_parameters(ReplyParameters::new(msg.id))
+                .await?;
+        }
+        Command::Settings(args) => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            change_settings(&bot, &msg, sender_id, args.trim(), &config).await?;
+        }
+        Command::Safety(args) => {
+            let sender_id = msg.from.as_ref().unwrap().id;
+            change_safety(&bot, &msg, sender_id, args.trim(), &config).await?;
+        }
+        Command::SetKey(key) => {
+            set_api_key(&bot, &msg, key.trim(), &config).await?;
+        }
+        Command::RemoveKey => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            let reply = api_key::remove_key(&config, sender_id).await;
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        Command::Backend(name) => {
+            let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+            let reply = set_backend(sender_id, name.trim(), &config).await;
+            bot.send_message(msg.chat.id, reply)
+                .reply_parameters(ReplyParameters::new(msg.id))
+                .await?;
         }
         Command::UsernameAndAge { username, age } => {
             bot.send_message(
@@ -137,11 +300,12 @@ async fn command_handler(
             send_developer_info(&bot, &msg).await?;
         }
         Command::NewTopic => {
-            let db = config.database.lock().await;
             let sender_id = msg.from.unwrap().id.0 as i64;
+            cache::delete(&config, sender_id).await;
+            let db = config.database.lock().await;
             match MessageHistory::delete_by_user_id(sender_id, &db).await {
                 Ok(()) => {
-                    let history = MessageHistory::new(sender_id as i64, Vec::new());
+                    let history = MessageHistory::new(sender_id, Vec::new());
                     history.insert(&db).await.ok();
                     bot.send_message(msg.chat.id, "Your previous topic has been flushed.")
                         .await?;
@@ -161,15 +325,427 @@ async fn command_handler(
     respond(())
 }
 
+async fn callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    config: Arc<AppConfig>,
+) -> ResponseResult<()> {
+    let data = query.data.clone().unwrap_or_default();
+    if let Some(model) = data.strip_prefix(MODEL_CALLBACK_PREFIX) {
+        select_model(&bot, &query, model, &config).await
+    } else if let Some(choice) = data.strip_prefix(settings::SETTINGS_CALLBACK_PREFIX) {
+        select_preset(&bot, &query, choice, &config).await
+    } else {
+        bot.answer_callback_query(&query.id).await?;
+        respond(())
+    }
+}
+
+async fn select_model(
+    bot: &Bot,
+    query: &CallbackQuery,
+    model: &str,
+    config: &Arc<AppConfig>,
+) -> ResponseResult<()> {
+    if !config.selectable_models.iter().any(|x| x == model) {
+        bot.answer_callback_query(&query.id)
+            .text("This model is not available anymore.")
+            .await?;
+        return respond(());
+    }
+
+    let sender_id = query.from.id.0 as i64;
+    {
+        let db = config.database.lock().await;
+        let mut settings = UserSettings::find_or_default(sender_id, &db).await.unwrap();
+        settings.model = Some(model.to_string());
+        if let Err(err) = settings.insert(&db).await {
+            log::error!("Error while saving user settings: {:?}", err);
+            bot.answer_callback_query(&query.id)
+                .text("Something didnt go well, please try again later.")
+                .await?;
+            return respond(());
+        }
+    }
+
+    bot.answer_callback_query(&query.id)
+        .text(format!("Now using {}", model))
+        .await?;
+    if let Some(message) = query.regular_message() {
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            format!("You are now using {}.", model),
+        )
+        .reply_markup(model_keyboard(&config.selectable_models, model))
+        .await?;
+    }
+
+    respond(())
+}
+
+/// Shows the generation settings with the preset keyboard, or changes one value when
+/// the command has arguments.
+async fn change_settings(
+    bot: &Bot,
+    msg: &Message,
+    sender_id: i64,
+    args: &str,
+    config: &Arc<AppConfig>,
+) -> ResponseResult<()> {
+    let db = config.database.lock().await;
+    let mut user_settings = match UserSettings::find_or_default(sender_id, &db).await {
+        Ok(user_settings) => user_settings,
+        Err(err) => {
+            log::error!("Failed to load user settings: {}", err);
+            bot.send_message(
+                msg.chat.id,
+                "Something didnt go well, please try again later.",
+            )
+            .await?;
+            return respond(());
+        }
+    };
+
+    let reply = if args.is_empty() {
+        Ok(settings::describe(&user_settings))
+    } else {
+        settings::set_parameter(&mut user_settings, args)
+    };
+    let reply = match reply {
+        Ok(text) if !args.is_empty() => match user_settings.insert(&db).await {
+            Ok(()) => text,
+            Err(err) => {
+                log::error!("Failed to save user settings: {}", err);
+                String::from("Something didnt go well, please try again later.")
+            }
+        },
+        Ok(text) | Err(text) => text,
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .reply_markup(settings::settings_keyboard(&user_settings))
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .await?;
+    respond(())
+}
+
+async fn select_preset(
+    bot: &Bot,
+    query: &CallbackQuery,
+    choice: &str,
+    config: &Arc<AppConfig>,
+) -> ResponseResult<()> {
+    let sender_id = query.from.id.0 as i64;
+    let user_settings = {
+        let db = config.database.lock().await;
+        let mut user_settings = UserSettings::find_or_default(sender_id, &db).await.unwrap();
+        if !settings::apply_choice(&mut user_settings, choice) {
+            bot.answer_callback_query(&query.id)
+                .text("This preset is not available anymore.")
+                .await?;
+            return respond(());
+        }
+        if let Err(err) = user_settings.insert(&db).await {
+            log::error!("Error while saving user settings: {:?}", err);
+            bot.answer_callback_query(&query.id)
+                .text("Something didnt go well, please try again later.")
+                .await?;
+            return respond(());
+        }
+        user_settings
+    };
+
+    bot.answer_callback_query(&query.id)
+        .text("Settings saved")
+        .await?;
+    if let Some(message) = query.regular_message() {
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            settings::describe(&user_settings),
+        )
+        .reply_markup(settings::settings_keyboard(&user_settings))
+        .await?;
+    }
+
+    respond(())
+}
+
+fn model_keyboard(models: &[String], current: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(models.iter().map(|model| {
+        let label = if model == current {
+            format!("✅ {}", model)
+        } else {
+            model.clone()
+        };
+        vec![InlineKeyboardButton::callback(
+            label,
+            format!("{}{}", MODEL_CALLBACK_PREFIX, model),
+        )]
+    }))
+}
+
+/// The model the user picked, or the default one.
+async fn current_model(sender_id: i64, config: &Arc<AppConfig>) -> String {
+    let preferred = user_model(sender_id, config).await;
+    config
+        .model_chain(preferred.as_deref())
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+async fn user_model(sender_id: i64, config: &Arc<AppConfig>) -> Option<String> {
+    user_settings(sender_id, config).await.model
+}
+
+/// Settings of the user, the defaults are used when they can't be loaded.
+async fn user_settings(sender_id: i64, config: &Arc<AppConfig>) -> UserSettings {
+    let db = config.database.lock().await;
+    match UserSettings::find_or_default(sender_id, &db).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("Error while loading user settings: {:?}", err);
+            UserSettings::new(sender_id)
+        }
+    }
+}
+
+/// Options for answering a message of the user in the given chat.
+async fn chat_options(sender_id: i64, chat_id: i64, config: &Arc<AppConfig>) -> GenerateOptions {
+    let settings = user_settings(sender_id, config).await;
+    let chat_settings = {
+        let db = config.database.lock().await;
+        match ChatSettings::find_or_default(chat_id, &db).await {
+            Ok(chat_settings) => chat_settings,
+            Err(err) => {
+                log::error!("Error while loading chat settings: {:?}", err);
+                ChatSettings::new(chat_id)
+            }
+        }
+    };
+
+    // The private chat with a user has the user's id
+    let limit = safety_limit(config, chat_id == sender_id);
+
+    GenerateOptions {
+        model: settings.model.clone(),
+        tool_user: Some(sender_id),
+        grounding: settings.grounding,
+        generation_config: settings.generation_config(),
+        safety_settings: safety::request_settings(&chat_settings, limit),
+        conversation: Some(sender_id),
+        api_key: api_key::user_key(config, sender_id).await,
+        backend: settings.backend(),
+    }
+}
+
+/// Deletes the message holding the key before anything else, then stores the key.
+/// Keys are only taken in private chats, a key posted in a group is as good as public.
+async fn set_api_key(
+    bot: &Bot,
+    msg: &Message,
+    key: &str,
+    config: &Arc<AppConfig>,
+) -> ResponseResult<()> {
+    if !key.is_empty() {
+        if let Err(err) = bot.delete_message(msg.chat.id, msg.id).await {
+            log::warn!("Could not delete a message with an API key: {}", err);
+        }
+    }
+
+    let reply = if !msg.chat.is_private() {
+        if key.is_empty() {
+            String::from("Send /setkey with your key in a private chat with me.")
+        } else {
+            String::from(
+                "Keys are only accepted in a private chat with me. Anyone in this group may have seen it, \
+please revoke it in Google AI Studio and create a new one.",
+            )
+        }
+    } else {
+        let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+        api_key::set_key(config, sender_id, key)
+            .await
+            .unwrap_or_else(|text| text)
+    };
+
+    bot.send_message(msg.chat.id, reply).await?;
+    respond(())
+}
+
+/// Loosest safety threshold allowed in a chat, only private chats are limited.
+fn safety_limit(config: &AppConfig, private: bool) -> Option<&str> {
+    private.then_some(config.private_safety_limit.as_str())
+}
+
+/// Shows the safety filters of the chat, or changes them when the command has arguments.
+/// Only admins can change them in groups, private chats are bound to the operator's limit.
+async fn change_safety(
+    bot: &Bot,
+    msg: &Message,
+    sender_id: UserId,
+    args: &str,
+    config: &Arc<AppConfig>,
+) -> ResponseResult<()> {
+    let limit = safety_limit(config, msg.chat.is_private());
+    // Anonymous admins post as the group itself
+    let anonymous_admin = msg.sender_chat.as_ref().map(|x| x.id) == Some(msg.chat.id);
+    if !args.is_empty() && !msg.chat.is_private() && !anonymous_admin {
+        let member = bot.get_chat_member(msg.chat.id, sender_id).await?;
+        if !member.is_privileged() {
+            bot.send_message(
+                msg.chat.id,
+                "Only group admins can change the safety filters.",
+            )
+            .reply_parameters(ReplyParameters::new(msg.id))
+            .await?;
+            return respond(());
+        }
+    }
+
+    let reply = {
+        let db = config.database.lock().await;
+        match ChatSettings::find_or_default(msg.chat.id.0, &db).await {
+            Ok(mut chat_settings) if !args.is_empty() => {
+                match safety::set_threshold(&mut chat_settings, args, limit) {
+                    Ok(text) => match chat_settings.insert(&db).await {
+                        Ok(()) => text,
+                        Err(err) => {
+                            log::error!("Failed to save chat settings: {}", err);
+                            String::from("Something didnt go well, please try again later.")
+                        }
+                    },
+                    Err(text) => text,
+                }
+            }
+            Ok(chat_settings) => safety::describe(&chat_settings, limit),
+            Err(err) => {
+                log::error!("Failed to load chat settings: {}", err);
+                String::from("Something didnt go well, please try again later.")
+            }
+        }
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .await?;
+    respond(())
+}
+
+/// Switches Google Search grounding for the user and returns the answer for them.
+async fn toggle_grounding(sender_id: i64, config: &Arc<AppConfig>) -> String {
+    let db = config.database.lock().await;
+    let mut settings = match UserSettings::find_or_default(sender_id, &db).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("Failed to load user settings: {}", err);
+            return String::from("Something didnt go well, please try again later.");
+        }
+    };
+
+    settings.grounding = !settings.grounding;
+    match settings.insert(&db).await {
+        Ok(()) if settings.grounding => String::from(
+            "Google Search grounding is on, answers are based on current search results and list their sources.",
+        ),
+        Ok(()) => String::from("Google Search grounding is off."),
+        Err(err) => {
+            log::error!("Failed to save grounding setting: {}", err);
+            String::from("Something didnt go well, please try again later.")
+        }
+    }
+}
+
+/// Saves the timezone used by the date and time tool and returns the answer for the user.
+/// Without an argument it only reports the current timezone.
+async fn set_timezone(sender_id: i64, timezone: &str, config: &Arc<AppConfig>) -> String {
+    let db = config.database.lock().await;
+    let mut settings = match UserSettings::find_or_default(sender_id, &db).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("Failed to load user settings: {}", err);
+            return String::from("Something didnt go well, please try again later.");
+        }
+    };
+
+    if timezone.is_empty() {
+        return format!(
+            "Your timezone is {}. Change it with /timezone Area/City, e.g. /timezone Europe/Berlin.",
+            settings.timezone.as_deref().unwrap_or("UTC")
+        );
+    }
+
+    let tz = match timezone.parse::<chrono_tz::Tz>() {
+        Ok(tz) => tz,
+        Err(_) => {
+            return format!(
+                "{} is not a known timezone, use a name like Europe/Berlin or America/New_York.",
+                timezone
+            )
+        }
+    };
+
+    settings.timezone = Some(tz.name().to_string());
+    match settings.insert(&db).await {
+        Ok(()) => format!("Your timezone is now {}.", tz.name()),
+        Err(err) => {
+            log::error!("Failed to save timezone: {}", err);
+            String::from("Something didnt go well, please try again later.")
+        }
+    }
+}
+
+/// Saves the backend the user picked and returns the answer for the user.
+/// Without an argument it only reports the backend in use.
+async fn set_backend(sender_id: i64, name: &str, config: &Arc<AppConfig>) -> String {
+    let db = config.database.lock().await;
+    let mut settings = match UserSettings::find_or_default(sender_id, &db).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("Failed to load user settings: {}", err);
+            return String::from("Something didnt go well, please try again later.");
+        }
+    };
+
+    let current = config.backend(settings.backend());
+    if config.openai.is_none() {
+        return format!(
+            "Your requests are answered by {}, no other backend is configured.",
+            current.name()
+        );
+    }
+    if name.is_empty() {
+        return format!(
+            "Your requests are answered by {}. Switch with /backend gemini or /backend openai.",
+            current.name()
+        );
+    }
+
+    let backend = match name.parse::<BackendKind>() {
+        Ok(backend) => backend,
+        Err(_) => return format!("{} is not a backend, use gemini or openai.", name),
+    };
+    settings.backend = Some(backend.name().to_string());
+    match settings.insert(&db).await {
+        Ok(()) => format!("Your requests are now answered by {}.", backend.name()),
+        Err(err) => {
+            log::error!("Failed to save backend: {}", err);
+            String::from("Something didnt go well, please try again later.")
+        }
+    }
+}
+
 async fn send_developer_info(bot: &Bot, msg: &Message) -> ResponseResult<()> {
-    let profile_link = format!("tg://user?id=6057706319");
-    let github_link = format!("https://github.com/mahyarkhn");
+    let profile_link = "tg://user?id=6057706319";
+    let github_link = "https://github.com/mahyarkhn";
 
     let text = format!(
         "ZenithGemini created by {}\r\n{}\r\n{}\r\nContact me at {}",
         html::bold("MahyarKhn"),
-        html::link(&profile_link, "View Profile"),
-        html::link(&github_link, "View Github"),
+        html::link(profile_link, "View Profile"),
+        html::link(github_link, "View Github"),
         html::italic("mahyarkhn@proton.me"),
     );
 
@@ -180,102 +756,209 @@ async fn send_developer_info(bot: &Bot, msg: &Message) -> ResponseResult<()> {
     respond(())
 }
 
-async fn generate_response(
+pub async fn generate_response(
     bot: Bot,
     msg: &Message,
     text: String,
+    attachments: Vec<Attachment>,
     config: Arc<AppConfig>,
+    backend: &Backend,
 ) -> ResponseResult<()> {
-    let response_message = bot
-        .send_message(
-            msg.chat_id().unwrap(),
-            escape_markdown("🔮 *Please stay patient...*"),
-        )
-        .reply_parameters(ReplyParameters::new(msg.id))
-        .parse_mode(teloxide::types::ParseMode::MarkdownV2)
-        .await
-        .unwrap();
+    let response_message = send_placeholder(&bot, msg).await?;
 
-    let mut history_data: Option<Vec<(String, String)>> = None;
     let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
-    {
-        let db = &config.database.lock().await;
-        let histroy = MessageHistory::find_by_user_id(sender_id, &db)
-            .await
-            .unwrap();
-        if let Some(history) = histroy {
-            if history.messages.len() > 0 {
-                history_data = Some(Vec::new());
-                for i in history.messages {
-                    if let Some(message) = crate::models::message::Message::find_by_id(i, &db)
-                        .await
-                        .unwrap()
-                    {
-                        history_data
-                            .as_mut()
-                            .unwrap()
-                            .push((message.content.unwrap(), message.response.unwrap()));
-                    }
-                }
+    let options = chat_options(sender_id, msg.chat.id.0, &config).await;
+    let history_data = load_history(&bot, sender_id, &options, &config, backend).await;
+
+    let uploads = config.uses_files_api(&options);
+    let mut parts = Vec::new();
+    for attachment in &attachments {
+        match media::attachment_part(&bot, &config, attachment, uploads).await {
+            Ok(part) => parts.push(part),
+            Err(err) => {
+                log::error!("Failed to download attachment: {}", err);
+                return send_failure(
+                    &bot,
+                    &response_message,
+                    "Could not download your file, please try again.",
+                )
+                .await;
             }
         }
     }
 
-    let gemini_response = query_gemini_api(&Box::from(text.as_str()), &Arc::from(None), &config, &Arc::from(history_data)).await;
+    let (updates, receiver) = mpsc::unbounded_channel();
+    let editor = spawn_stream_editor(bot.clone(), msg.chat.id, response_message.id, receiver);
+    let prompt = Prompt {
+        query: &text,
+        attachments: &parts,
+        instructions: &Arc::from(None),
+        history: &Arc::from(history_data),
+    };
+    let gemini_response = backend.stream(&config, prompt, &options, updates).await;
+    _ = editor.await;
 
-    {
-        let db = &config.database.lock().await;
-        let mut histroy = MessageHistory::find_by_user_id(sender_id, &db)
-            .await
-            .unwrap();
-        if let None = &histroy {
-            histroy = Some(MessageHistory::new(sender_id, Vec::new()));
-            _ = histroy.as_ref().unwrap().insert(&db).await;
+    let reply = match gemini_response {
+        Ok(reply) => {
+            log::debug!(
+                "Response generated by {} ({:?}), usage: {:?}",
+                reply.model,
+                reply.model_version,
+                reply.usage_metadata
+            );
+            reply
         }
-        let message = crate::models::message::Message::new(
-            msg.chat.id.0 as i64,
+        Err(err) => {
+            log::error!("Failed to generate response: {}", err);
+            return send_failure(&bot, &response_message, &error_message(&err)).await;
+        }
+    };
+
+    store_in_history(
+        crate::models::message::Message::new(
+            msg.chat.id.0,
             sender_id,
             msg.id.0 as i64,
             Some(text.to_string()),
-            Some(gemini_response.to_string()),
+            Some(reply.text.clone()),
             msg.date.timestamp(),
-        );
-        _ = message.insert(&db).await;
-        let _ = match crate::models::message::Message::find_by_message_and_chat_id(
+        )
+        .with_attachments(attachments),
+        &config,
+    )
+    .await;
+
+    send_reply(&bot, msg, &response_message, &reply.render()).await?;
+    send_images(&bot, msg, &reply).await
+}
+
+/// Adds a document sent without a caption to the conversation and asks what to do with it,
+/// the next question in the topic is answered with the document as context.
+async fn ask_about_document(
+    bot: Bot,
+    msg: &Message,
+    attachment: Attachment,
+    config: Arc<AppConfig>,
+) -> ResponseResult<()> {
+    let file_name = msg
+        .document()
+        .and_then(|x| x.file_name.clone())
+        .unwrap_or(String::from("a file"));
+    let question = format!(
+        "I received {}, what would you like me to do with it?",
+        file_name
+    );
+
+    bot.send_message(msg.chat.id, &question)
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .await?;
+
+    let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+    store_in_history(
+        crate::models::message::Message::new(
+            msg.chat.id.0,
+            sender_id,
             msg.id.0 as i64,
-            msg.chat.id.0 as i64,
-            &db,
+            Some(format!("Here is {}.", file_name)),
+            Some(question),
+            msg.date.timestamp(),
         )
-        .await
-        .unwrap()
-        {
-            Some(msg) => {
-                histroy.as_mut().unwrap().messages.push(msg.id);
-                _ = histroy.as_ref().unwrap().update(&db).await;
-                Some(msg)
-            }
-            None => {
-                log::error!("Failed to store message in history! ");
-                None
-            }
-        };
+        .with_attachments(vec![attachment]),
+        &config,
+    )
+    .await;
+
+    respond(())
+}
+
+/// Replies with the transcript of the audio, transcripts are not part of the conversation history.
+async fn transcribe(
+    bot: &Bot,
+    msg: &Message,
+    audio: &Attachment,
+    config: &Arc<AppConfig>,
+    backend: &Backend,
+) -> ResponseResult<()> {
+    let sender_id = msg.from.as_ref().unwrap().id.0 as i64;
+    // The filters of the chat apply, but a transcript needs no tools, sampling changes or history
+    let options = GenerateOptions {
+        tool_user: None,
+        grounding: false,
+        generation_config: None,
+        conversation: None,
+        ..chat_options(sender_id, msg.chat.id.0, config).await
+    };
+    // Other backends never receive the audio and would make a transcript up
+    if config.backend(options.backend) != BackendKind::Gemini {
+        bot.send_message(
+            msg.chat.id,
+            "Transcription needs Gemini, switch to it with /backend gemini.",
+        )
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .await?;
+        return respond(());
+    }
+
+    let response_message = send_placeholder(bot, msg).await?;
+    let uploads = config.uses_files_api(&options);
+    let part = match media::attachment_part(bot, config, audio, uploads).await {
+        Ok(part) => part,
+        Err(err) => {
+            log::error!("Failed to download audio: {}", err);
+            return send_failure(
+                bot,
+                &response_message,
+                "Could not download the voice message, please try again.",
+            )
+            .await;
+        }
+    };
+    let prompt = Prompt {
+        query: TRANSCRIBE_PROMPT,
+        attachments: &[part],
+        instructions: &Arc::from(None),
+        history: &Arc::from(None),
+    };
+    let transcript = backend.generate(config, prompt, &options).await;
+
+    match transcript {
+        Ok(reply) => send_reply(bot, msg, &response_message, &reply.text).await,
+        Err(err) => {
+            log::error!("Failed to transcribe audio: {}", err);
+            send_failure(bot, &response_message, &error_message(&err)).await
+        }
     }
+}
 
-    if gemini_response.len() >= 4096 {
+async fn send_placeholder(bot: &Bot, msg: &Message) -> ResponseResult<Message> {
+    bot.send_message(msg.chat.id, escape_markdown("🔮 *Please stay patient...*"))
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .parse_mode(teloxide::types::ParseMode::MarkdownV2)
+        .await
+}
+
+/// Replaces the placeholder with the final text, or sends it as a file if it is too long for a message.
+async fn send_reply(
+    bot: &Bot,
+    msg: &Message,
+    response_message: &Message,
+    text: &str,
+) -> ResponseResult<()> {
+    if text.len() >= TELEGRAM_MESSAGE_LIMIT {
         let file_path = std::env::temp_dir()
             .join(std::path::Path::new(&format!("{}.txt", msg.chat.id)))
             .display()
             .to_string();
 
         if let Ok(mut file) = File::create(&file_path).await {
-            file.write_all(gemini_response.as_bytes()).await?;
+            file.write_all(text.as_bytes()).await?;
             file.flush().await?;
             file.shutdown().await?;
 
             bot.send_document(msg.chat.id, InputFile::file(&file_path))
-                    .caption(format!(
+                    .caption(
                         "Due to limitations of telegram we had to generate a text file for your response.\r\n\r\n🌟 _*@zenithgeminibot*_",
-                    ))
+                    )
                     .parse_mode(teloxide::types::ParseMode::MarkdownV2)
                     .reply_parameters(ReplyParameters::new(msg.id))
                     .await?;
@@ -283,45 +966,232 @@ async fn generate_response(
             tokio::fs::remove_file(&file_path).await?;
         } else {
             bot.edit_message_text(
-                    msg.chat_id().unwrap(),
+                    msg.chat.id,
                     response_message.id,
-                    format!(
-                        "Something went wrong while generating you answer...\r\n\r\n❌ _*@zenithgeminibot*_",
-                    ),
+                    "Something went wrong while generating you answer...\r\n\r\n❌ _*@zenithgeminibot*_",
                 )
                 .parse_mode(teloxide::types::ParseMode::MarkdownV2)
                 .await?;
         }
     } else {
-        // let res = bot
-        //     .edit_message_text(
-        //         msg.chat_id().unwrap(),
-        //         response_message.id,
-        //         format!("{}\r\n\r\n🌟 _*@zenithgeminibot*_", gemini_response),
-        //     )
-        //     .parse_mode(teloxide::types::ParseMode::MarkdownV2)
-        //     .await;
-        // if res.is_err() {
-            // markdown especial chars may create error sometimes
-            bot.edit_message_text(
-                msg.chat_id().unwrap(),
-                response_message.id,
-                format!("{}\r\n\r\n🌟 @zenithgeminibot", gemini_response),
-            )
+        // markdown especial chars may create error sometimes, so the answer is sent as plain text
+        bot.edit_message_text(
+            msg.chat.id,
+            response_message.id,
+            format!("{}\r\n\r\n🌟 @zenithgeminibot", text),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Sends the images of the reply as photos, answering the original message.
+async fn send_images(bot: &Bot, msg: &Message, reply: &GeminiReply) -> ResponseResult<()> {
+    for (_, data) in reply.images() {
+        bot.send_photo(msg.chat.id, InputFile::memory(data.to_vec()))
+            .reply_parameters(ReplyParameters::new(msg.id))
             .await?;
-        // }
     }
+    Ok(())
+}
 
+/// Replaces the placeholder with an error message.
+async fn send_failure(bot: &Bot, response_message: &Message, text: &str) -> ResponseResult<()> {
+    bot.edit_message_text(
+        response_message.chat.id,
+        response_message.id,
+        format!("{}\r\n\r\n❌ @zenithgeminibot", text),
+    )
+    .await?;
     Ok(())
 }
 
+/// Loads the sender's current topic, summarizing its oldest turns when it outgrows the token budget.
+async fn load_history(
+    bot: &Bot,
+    sender_id: i64,
+    options: &GenerateOptions,
+    config: &Arc<AppConfig>,
+    backend: &Backend,
+) -> Option<Vec<HistoryTurn>> {
+    let mut messages = Vec::new();
+    let mut summary = None;
+    {
+        let db = &config.database.lock().await;
+        let histroy = MessageHistory::find_by_user_id(sender_id, db)
+            .await
+            .unwrap();
+        if let Some(history) = histroy {
+            summary = history.summary;
+            for i in history.messages {
+                if let Some(message) = crate::models::message::Message::find_by_id(i, db)
+                    .await
+                    .unwrap()
+                {
+                    messages.push(message);
+                }
+            }
+        }
+    }
+
+    if messages.is_empty() && summary.is_none() {
+        return None;
+    }
+
+    let uploads = config.uses_files_api(options);
+    let mut history_data = Vec::new();
+    for message in &messages {
+        let mut turn = HistoryTurn::new(
+            message.content.clone().unwrap(),
+            message.response.clone().unwrap(),
+        );
+        for attachment in &message.attachments {
+            match media::attachment_part(bot, config, attachment, uploads).await {
+                Ok(part) => turn.attachments.push(part),
+                Err(err) => log::warn!("Skipping attachment of message {}: {}", message.id, err),
+            }
+        }
+        history_data.push(turn);
+    }
+
+    let fitted =
+        history::fit_to_budget(config, backend.as_ref(), options, summary, history_data).await;
+    if fitted.summarized > 0 {
+        let summarized: Vec<i64> = messages[..fitted.summarized].iter().map(|x| x.id).collect();
+        let db = &config.database.lock().await;
+        if let Ok(Some(mut history)) = MessageHistory::find_by_user_id(sender_id, db).await {
+            history.messages.retain(|x| !summarized.contains(x));
+            history.summary = fitted.summary;
+            if let Err(err) = history.update(db).await {
+                log::error!("Failed to store the history summary: {}", err);
+            }
+        }
+    }
+    Some(fitted.turns)
+}
+
+/// User facing explanation of a failed request.
+fn error_message(err: &GeminiError) -> String {
+    match err {
+        GeminiError::Transport(_) => {
+            String::from("Could not reach Gemini, please try again in a moment.")
+        }
+        GeminiError::HttpStatus { status, .. } => format!(
+            "Gemini could not process your request (error {}), please try again later.",
+            status
+        ),
+        GeminiError::Quota { .. } => {
+            String::from("Too many requests right now, please try again in a minute.")
+        }
+        GeminiError::SafetyBlocked {
+            category: Some(category),
+            ..
+        } => format!(
+            "Your request was blocked by Gemini's safety filters because of {} content. \
+            The filters can be adjusted with /safety.",
+            crate::gemini::safety::short_name(category)
+        ),
+        GeminiError::SafetyBlocked { reason, .. } => format!(
+            "Your request was blocked by Gemini's safety filters ({}).",
+            reason
+        ),
+        GeminiError::EmptyCandidates => {
+            String::from("Gemini did not return an answer, try rephrasing your question.")
+        }
+        GeminiError::MalformedBody(_) => {
+            String::from("Gemini returned an unexpected response, please try again later.")
+        }
+        GeminiError::FileProcessing(_) => {
+            String::from("Gemini could not process your file, please try another one.")
+        }
+        GeminiError::NoApiKey => {
+            String::from("This is not available with the bot's current setup.")
+        }
+        GeminiError::Signing => {
+            String::from("Could not sign in to Gemini, please try again later.")
+        }
+    }
+}
+
+/// Stores the exchange and appends it to the sender's current topic.
+async fn store_in_history(message: crate::models::message::Message, config: &Arc<AppConfig>) {
+    let db = &config.database.lock().await;
+    let mut histroy = MessageHistory::find_by_user_id(message.sender_id, db)
+        .await
+        .unwrap();
+    if histroy.is_none() {
+        _ = MessageHistory::new(message.sender_id, Vec::new())
+            .insert(db)
+            .await;
+        histroy = MessageHistory::find_by_user_id(message.sender_id, db)
+            .await
+            .unwrap();
+    }
+    _ = message.insert(db).await;
+    match crate::models::message::Message::find_by_message_and_chat_id(
+        message.message_id,
+        message.chat_id,
+        db,
+    )
+    .await
+    .unwrap()
+    {
+        Some(msg) => {
+            histroy.as_mut().unwrap().messages.push(msg.id);
+            _ = histroy.as_ref().unwrap().update(db).await;
+        }
+        None => {
+            log::error!("Failed to store message in history! ");
+        }
+    };
+}
+
+/// Edits the placeholder message with the partial response while it is being streamed.
+/// Edits are throttled by `STREAM_EDIT_INTERVAL` and stop once the text no longer fits in a message.
+fn spawn_stream_editor(
+    bot: Bot,
+    chat_id: ChatId,
+    message_id: teloxide::types::MessageId,
+    mut receiver: mpsc::UnboundedReceiver<String>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_edit = Instant::now();
+        let mut last_text = String::new();
+
+        while let Some(mut text) = receiver.recv().await {
+            while let Ok(newer) = receiver.try_recv() {
+                text = newer;
+            }
+            if text.trim().is_empty()
+                || text == last_text
+                || last_edit.elapsed() < STREAM_EDIT_INTERVAL
+            {
+                continue;
+            }
+
+            let partial = format!("{}\r\n\r\n🔮 ...", text);
+            if partial.len() >= TELEGRAM_MESSAGE_LIMIT {
+                break;
+            }
+            if let Err(err) = bot.edit_message_text(chat_id, message_id, partial).await {
+                log::warn!("Could not edit streamed response: {}", err);
+            }
+            last_edit = Instant::now();
+            last_text = text;
+        }
+    })
+}
+
+/// Answers an inline query ending with `!!` after the user stopped typing for a second,
+/// a query still being typed replaces the pending one.
 async fn inline_handler(
     bot: Bot,
     query: InlineQuery,
     config: Arc<AppConfig>,
+    backend: Backend,
     user_states: UserStates,
 ) -> ResponseResult<()> {
-    todo!("Fix stackoverflow problem");
     let user_id = query.from.id.0;
     let new_query = query.query.clone();
 
@@ -353,6 +1223,7 @@ async fn inline_handler(
                 user_id as i64,
                 current_query,
                 config,
+                backend,
                 user_states_clone,
             )
             .await;
@@ -364,88 +1235,62 @@ async fn inline_handler(
     respond(())
 }
 
+/// Caps the answer length, keeping a lower limit chosen by the user.
+fn limit_output_tokens(options: &mut GenerateOptions, limit: u32) {
+    let generation_config = options
+        .generation_config
+        .get_or_insert_with(Default::default);
+    generation_config.max_output_tokens = Some(
+        generation_config
+            .max_output_tokens
+            .map_or(limit, |x| x.min(limit)),
+    );
+}
+
 async fn process_gemini_request(
     bot: &Bot,
     query_id: String,
     user_id: i64,
     query_text: String,
     config: Arc<AppConfig>,
+    backend: Backend,
     user_states: UserStates,
 ) {
-    let mut history_data: Option<Vec<(String, String)>> = None;
-    let sender_id = user_id as i64;
-    {
-        let db = &config.database.lock().await;
-        let histroy = MessageHistory::find_by_user_id(sender_id, &db)
-            .await
-            .unwrap();
-        if let Some(history) = histroy {
-            if history.messages.len() > 0 {
-                history_data = Some(Vec::new());
-                for i in history.messages {
-                    if let Some(message) = crate::models::message::Message::find_by_id(i, &db)
-                        .await
-                        .unwrap()
-                    {
-                        history_data
-                            .as_mut()
-                            .unwrap()
-                            .push((message.content.unwrap(), message.response.unwrap()));
-                    }
-                }
-            }
-        }
-    }
+    let sender_id = user_id;
+    // Inline queries have no chat, the filters of the private chat with the user apply
+    let mut options = chat_options(sender_id, sender_id, &config).await;
+    limit_output_tokens(&mut options, INLINE_MAX_OUTPUT_TOKENS);
+    let history_data = load_history(bot, sender_id, &options, &config, &backend).await;
 
-    let query_result = query_gemini_api(
-        &Box::from(query_text.as_str()),
-        &Arc::from(Some(vec![
-            "be extra precise",
-            "do not exceed 4700 chars at any chance",
-        ])),
-        &config,
-        &Arc::from(history_data),
-    )
-    .await;
+    let prompt = Prompt {
+        query: &query_text,
+        attachments: &[],
+        instructions: &Arc::from(Some(vec!["be extra precise"])),
+        history: &Arc::from(history_data),
+    };
+    let query_result = backend.generate(&config, prompt, &options).await;
 
-    {
-        let db = &config.database.lock().await;
-        let mut histroy = MessageHistory::find_by_user_id(sender_id, &db)
-            .await
-            .unwrap();
-        if let None = &histroy {
-            histroy = Some(MessageHistory::new(sender_id, Vec::new()));
-            _ = histroy.as_ref().unwrap().insert(&db).await;
+    let query_result = match query_result {
+        Ok(reply) => {
+            store_in_history(
+                crate::models::message::Message::new(
+                    sender_id,
+                    sender_id,
+                    0,
+                    Some(query_text.clone()),
+                    Some(reply.text.clone()),
+                    unix_timestamp(),
+                ),
+                &config,
+            )
+            .await;
+            reply.render()
         }
-        let _q = query_text.clone();
-        let message = crate::models::message::Message::new(
-            sender_id,
-            sender_id,
-            0,
-            Some(_q),
-            Some(query_result.to_string()),
-            unix_timestamp(),
-        );
-        _ = message.insert(&db).await;
-        let _ = match crate::models::message::Message::find_by_message_and_chat_id(
-            sender_id,
-            sender_id,
-            &db,
-        )
-        .await
-        .unwrap()
-        {
-            Some(msg) => {
-                histroy.as_mut().unwrap().messages.push(msg.id);
-                _ = histroy.as_ref().unwrap().update(&db).await;
-                Some(msg)
-            }
-            None => {
-                log::error!("Failed to store message in history! ");
-                None
-            }
-        };
-    }
+        Err(err) => {
+            log::error!("Failed to generate inline response: {}", err);
+            error_message(&err)
+        }
+    };
 
     let mut current_query_trimmed = query_text.clone();
     current_query_trimmed.truncate(current_query_trimmed.len() - 2);
@@ -457,12 +1302,10 @@ async fn process_gemini_request(
             &current_query_trimmed,
             utils::string::truncate_text(&query_result, 100)
         ),
-        InputMessageContent::Text(
-            InputMessageContentText::new(format!(
-                "{}\r\n\r\n🌟 @zenithgeminibot",
-                query_result
-            ))
-        ),
+        InputMessageContent::Text(InputMessageContentText::new(format!(
+            "{}\r\n\r\n🌟 @zenithgeminibot",
+            query_result
+        ))),
     )
     .into()];
 
@@ -0,0 +1,161 @@
+// Media module, downloads telegram files so they can be sent to Gemini
+use crate::app::config::AppConfig;
+use crate::gemini::{error::GeminiError, files};
+use crate::models::gemini::Part;
+use crate::models::message::Attachment;
+use std::fmt;
+use std::sync::Arc;
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::{DownloadError, RequestError};
+
+/// Telegram re-encodes every photo as JPEG.
+pub const PHOTO_MIME_TYPE: &str = "image/jpeg";
+/// Voice notes are recorded as OGG/Opus.
+pub const VOICE_MIME_TYPE: &str = "audio/ogg";
+const AUDIO_MIME_TYPE: &str = "audio/mpeg";
+
+/// Bots can not download files larger than this through the Bot API.
+pub const TELEGRAM_DOWNLOAD_LIMIT: u32 = 20 * 1024 * 1024;
+/// Larger files go through the Files API, requests with inline data are limited to 20MB in total
+/// and base64 makes the payload a third bigger.
+pub const MAX_INLINE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Extensions of text files telegram usually sends as `application/octet-stream`.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "csv", "tsv", "json", "xml", "yaml", "yml", "toml", "ini", "cfg",
+    "conf", "log", "rs", "py", "js", "ts", "tsx", "jsx", "java", "kt", "c", "h", "cpp", "hpp",
+    "cc", "cs", "go", "rb", "php", "swift", "sh", "bash", "sql", "html", "css", "scss", "lua",
+    "scala", "dart", "ex", "hs", "vue",
+];
+
+#[derive(Debug)]
+pub enum MediaError {
+    Request(RequestError),
+    Download(DownloadError),
+    Upload(GeminiError),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::Request(err) => write!(f, "could not get file: {}", err),
+            MediaError::Download(err) => write!(f, "could not download file: {}", err),
+            MediaError::Upload(err) => write!(f, "could not upload file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {}
+
+/// The largest size of the photo in the message, if there is one.
+pub fn photo_attachment(msg: &Message) -> Option<Attachment> {
+    let photo = msg.photo()?.iter().max_by_key(|x| x.width * x.height)?;
+    Some(Attachment::new(
+        &photo.file.id,
+        &photo.file.unique_id,
+        PHOTO_MIME_TYPE,
+    ))
+}
+
+/// The voice note or audio file in the message, if there is one.
+pub fn audio_attachment(msg: &Message) -> Option<Attachment> {
+    if let Some(voice) = msg.voice() {
+        let mime_type = voice.mime_type.as_ref().map(|x| x.essence_str());
+        return Some(Attachment::new(
+            &voice.file.id,
+            &voice.file.unique_id,
+            mime_type.unwrap_or(VOICE_MIME_TYPE),
+        ));
+    }
+
+    let audio = msg.audio()?;
+    let mime_type = audio.mime_type.as_ref().map(|x| x.essence_str());
+    Some(Attachment::new(
+        &audio.file.id,
+        &audio.file.unique_id,
+        mime_type.unwrap_or(AUDIO_MIME_TYPE),
+    ))
+}
+
+/// The document in the message, if it is a kind of file Gemini can read.
+pub fn document_attachment(msg: &Message) -> Option<Attachment> {
+    let document = msg.document()?;
+    let mime_type = document_mime_type(
+        document.mime_type.as_ref().map(|x| x.essence_str()),
+        document.file_name.as_deref(),
+    )?;
+    Some(Attachment::new(
+        &document.file.id,
+        &document.file.unique_id,
+        &mime_type,
+    ))
+}
+
+/// Mime type to send a document as, text and source files are all sent as plain text.
+pub fn document_mime_type(mime_type: Option<&str>, file_name: Option<&str>) -> Option<String> {
+    let mime_type = mime_type.unwrap_or("application/octet-stream");
+    let extension = file_name
+        .and_then(|x| x.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase());
+
+    if mime_type == "application/pdf"
+        || mime_type.starts_with("image/")
+        || mime_type.starts_with("audio/")
+        || mime_type.starts_with("video/")
+    {
+        Some(mime_type.to_string())
+    } else if mime_type.starts_with("text/")
+        || matches!(
+            mime_type,
+            "application/json" | "application/xml" | "application/javascript" | "application/x-sh"
+        )
+        || extension.is_some_and(|x| TEXT_EXTENSIONS.contains(&x.as_str()))
+    {
+        Some(String::from("text/plain"))
+    } else {
+        None
+    }
+}
+
+pub async fn download(bot: &Bot, file_id: &str) -> Result<Vec<u8>, MediaError> {
+    let file = bot.get_file(file_id).await.map_err(MediaError::Request)?;
+
+    let mut data = Vec::with_capacity(file.size as usize);
+    bot.download_file(&file.path, &mut data)
+        .await
+        .map_err(MediaError::Download)?;
+    Ok(data)
+}
+
+/// Downloads the attachment and turns it into an inline part,
+/// or uploads it through the Files API if it is too large to be sent inline.
+/// Media that was uploaded before is reused without downloading it again.
+/// Without `uploads`, for backends that can't read Gemini files, it is always inline.
+pub async fn attachment_part(
+    bot: &Bot,
+    config: &Arc<AppConfig>,
+    attachment: &Attachment,
+    uploads: bool,
+) -> Result<Part, MediaError> {
+    if uploads {
+        if let Some(file) = files::find_active(config, &attachment.file_unique_id).await {
+            return Ok(Part::file_data(&file.mime_type, &file.file_uri));
+        }
+    }
+
+    let data = download(bot, &attachment.file_id).await?;
+    if !uploads || data.len() <= MAX_INLINE_SIZE {
+        return Ok(Part::inline_data(&attachment.mime_type, &data));
+    }
+
+    let file = files::upload_and_track(
+        config,
+        &attachment.file_unique_id,
+        data,
+        &attachment.mime_type,
+    )
+    .await
+    .map_err(MediaError::Upload)?;
+    Ok(Part::file_data(&file.mime_type, &file.file_uri))
+}
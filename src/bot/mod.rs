@@ -1 +1,5 @@
+pub mod api_key;
 pub mod bot_logic;
+pub mod media;
+pub mod safety;
+pub mod settings;
@@ -0,0 +1,131 @@
+// Safety filters of a chat, changed with /safety
+use crate::gemini::safety::{self, CATEGORIES, THRESHOLDS};
+use crate::models::chat_settings::ChatSettings;
+use crate::models::gemini::SafetySetting;
+
+const USAGE: &str = "Change a filter with /safety <category> <threshold>, use all as the category to change every one, \
+default as the threshold to go back to the default, or /safety reset.";
+
+/// Handles `/safety <category> <threshold>`, returns the answer for the user or what was wrong.
+/// `limit` is the loosest threshold allowed, None when there is no limit. Where there is one, it
+/// is also the default of the categories left unset.
+pub fn set_threshold(
+    settings: &mut ChatSettings,
+    args: &str,
+    limit: Option<&str>,
+) -> Result<String, String> {
+    let args = args.trim();
+    if args.eq_ignore_ascii_case("reset") {
+        settings.safety_settings.clear();
+        return Ok(describe(settings, limit));
+    }
+
+    let (category, threshold) = args
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Missing threshold. {}", USAGE))?;
+    let categories: Vec<&str> = if category.eq_ignore_ascii_case("all") {
+        CATEGORIES.iter().map(|(_, api)| *api).collect()
+    } else {
+        vec![safety::parse_category(category).ok_or_else(|| {
+            format!(
+                "Unknown category {}, use one of: {}.",
+                category,
+                names(CATEGORIES)
+            )
+        })?]
+    };
+
+    let threshold = threshold.trim();
+    let threshold = if threshold.eq_ignore_ascii_case("default") {
+        None
+    } else {
+        let threshold = safety::parse_threshold(threshold).ok_or_else(|| {
+            format!(
+                "Unknown threshold {}, use one of: {}.",
+                threshold,
+                names(THRESHOLDS)
+            )
+        })?;
+        if let Some(limit) = limit.filter(|limit| !safety::within_limit(threshold, limit)) {
+            return Err(format!(
+                "The loosest threshold allowed here is {}.",
+                safety::short_name(limit)
+            ));
+        }
+        Some(threshold)
+    };
+
+    for category in categories {
+        settings.safety_settings.retain(|x| x.category != category);
+        if let Some(threshold) = threshold {
+            settings.safety_settings.push(SafetySetting {
+                category: category.to_string(),
+                threshold: threshold.to_string(),
+            });
+        }
+    }
+
+    Ok(describe(settings, limit))
+}
+
+/// Thresholds sent with the requests of the chat. With a limit, every category is sent and the
+/// unset ones are at the limit, the API defaults are looser than it for current models.
+/// Stored thresholds looser than the limit, e.g. from before it was tightened, are raised to it.
+pub fn request_settings(settings: &ChatSettings, limit: Option<&str>) -> Vec<SafetySetting> {
+    let Some(limit) = limit else {
+        return settings.safety_settings.clone();
+    };
+
+    CATEGORIES
+        .iter()
+        .map(|(_, category)| {
+            let threshold = settings
+                .safety_settings
+                .iter()
+                .find(|x| x.category == *category)
+                .map(|x| x.threshold.as_str())
+                .filter(|threshold| safety::within_limit(threshold, limit))
+                .unwrap_or(limit);
+            SafetySetting {
+                category: category.to_string(),
+                threshold: threshold.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// The threshold of every category, unset ones are at the limit or else left to the API.
+pub fn describe(settings: &ChatSettings, limit: Option<&str>) -> String {
+    let mut text = String::from("Safety filters of this chat:\n");
+    for (name, category) in CATEGORIES {
+        let threshold = settings
+            .safety_settings
+            .iter()
+            .find(|x| x.category == *category)
+            .map(|x| safety::short_name(&x.threshold))
+            .or(limit.map(safety::short_name))
+            .unwrap_or("default");
+        text.push_str(&format!("{}: {}\n", name, threshold));
+    }
+
+    text.push_str(&format!(
+        "\nThresholds from the strictest: {}. {}",
+        names(THRESHOLDS),
+        USAGE
+    ));
+    if let Some(limit) = limit {
+        text.push_str(&format!(
+            " The loosest threshold allowed here is {}, it is also the default.",
+            safety::short_name(limit)
+        ));
+    }
+    text
+}
+
+fn names(table: &[(&str, &str)]) -> String {
+    table
+        .iter()
+        .map(|(short, _)| *short)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,203 @@
+// Generation parameters and presets, changed with /settings
+use crate::models::user_settings::UserSettings;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Prefix of the callback data sent by the /settings keyboard.
+pub const SETTINGS_CALLBACK_PREFIX: &str = "settings:";
+/// Callback data of the button that goes back to the API defaults.
+const RESET: &str = "reset";
+
+const MAX_STOP_SEQUENCES: usize = 5;
+
+/// Named sampling parameters users can pick instead of tuning them one by one.
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub top_k: i64,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "precise",
+        description: "focused and repeatable answers",
+        temperature: 0.2,
+        top_p: 0.8,
+        top_k: 20,
+    },
+    Preset {
+        name: "balanced",
+        description: "a mix of accuracy and variety",
+        temperature: 0.7,
+        top_p: 0.95,
+        top_k: 40,
+    },
+    Preset {
+        name: "creative",
+        description: "more surprising, varied writing",
+        temperature: 1.4,
+        top_p: 0.98,
+        top_k: 64,
+    },
+];
+
+const USAGE: &str = "Change a single value with /settings <name> <value>, or use default as the value to unset it. \
+Names: temperature (0-2), top_p (0-1), top_k, max_tokens, stop (comma separated) and thinking_budget (-1 lets the model decide, 0 turns thinking off).";
+
+/// Applies the preset picked on the keyboard, returns false for unknown callback data.
+pub fn apply_choice(settings: &mut UserSettings, choice: &str) -> bool {
+    if choice == RESET {
+        settings.preset = None;
+        settings.temperature = None;
+        settings.top_p = None;
+        settings.top_k = None;
+        settings.max_output_tokens = None;
+        settings.stop_sequences = Vec::new();
+        settings.thinking_budget = None;
+        return true;
+    }
+
+    match PRESETS.iter().find(|x| x.name == choice) {
+        Some(preset) => {
+            settings.preset = Some(preset.name.to_string());
+            settings.temperature = Some(preset.temperature);
+            settings.top_p = Some(preset.top_p);
+            settings.top_k = Some(preset.top_k);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Handles `/settings <name> <value>`, returns the answer for the user or what was wrong.
+pub fn set_parameter(settings: &mut UserSettings, args: &str) -> Result<String, String> {
+    let (name, value) = args
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((args.trim(), ""));
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(format!("Missing value for {}. {}", name, USAGE));
+    }
+    let unset = value.eq_ignore_ascii_case("default");
+
+    match name.to_lowercase().as_str() {
+        "temperature" => {
+            settings.temperature = parse_value(value, unset, 0.0, 2.0)?;
+            settings.preset = None;
+        }
+        "top_p" => {
+            settings.top_p = parse_value(value, unset, 0.0, 1.0)?;
+            settings.preset = None;
+        }
+        "top_k" => {
+            settings.top_k = parse_value(value, unset, 1, 1000)?;
+            settings.preset = None;
+        }
+        "max_tokens" | "max_output_tokens" => {
+            settings.max_output_tokens = parse_value(value, unset, 1, 65536)?;
+        }
+        "thinking_budget" => {
+            settings.thinking_budget = parse_value(value, unset, -1, 32768)?;
+        }
+        "stop" | "stop_sequences" => {
+            let sequences: Vec<String> = if unset {
+                Vec::new()
+            } else {
+                value
+                    .split(',')
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty())
+                    .collect()
+            };
+            if sequences.len() > MAX_STOP_SEQUENCES {
+                return Err(format!(
+                    "At most {} stop sequences are allowed.",
+                    MAX_STOP_SEQUENCES
+                ));
+            }
+            settings.stop_sequences = sequences;
+        }
+        _ => return Err(format!("Unknown setting {}. {}", name, USAGE)),
+    }
+
+    Ok(describe(settings))
+}
+
+/// Parses a value within `min..=max`, None when the user asked for the default.
+fn parse_value<T>(value: &str, unset: bool, min: T, max: T) -> Result<Option<T>, String>
+where
+    T: std::str::FromStr + PartialOrd + std::fmt::Display,
+{
+    if unset {
+        return Ok(None);
+    }
+    match value.parse::<T>() {
+        Ok(x) if x >= min && x <= max => Ok(Some(x)),
+        _ => Err(format!(
+            "{} is not valid, use a number from {} to {}.",
+            value, min, max
+        )),
+    }
+}
+
+/// The current values, unset ones are left to the API.
+pub fn describe(settings: &UserSettings) -> String {
+    fn show<T: std::fmt::Display>(value: &Option<T>) -> String {
+        value
+            .as_ref()
+            .map(|x| x.to_string())
+            .unwrap_or(String::from("default"))
+    }
+
+    format!(
+        "Your generation settings ({}):\n\
+        temperature: {}\n\
+        top_p: {}\n\
+        top_k: {}\n\
+        max_tokens: {}\n\
+        stop: {}\n\
+        thinking_budget: {}\n\n\
+        Pick a preset below. {}",
+        match (&settings.preset, settings.generation_config()) {
+            (Some(preset), _) => preset.as_str(),
+            (None, None) => "API defaults",
+            (None, Some(_)) => "custom",
+        },
+        show(&settings.temperature),
+        show(&settings.top_p),
+        show(&settings.top_k),
+        show(&settings.max_output_tokens),
+        if settings.stop_sequences.is_empty() {
+            String::from("none")
+        } else {
+            settings.stop_sequences.join(", ")
+        },
+        show(&settings.thinking_budget),
+        USAGE
+    )
+}
+
+pub fn settings_keyboard(settings: &UserSettings) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = PRESETS
+        .iter()
+        .map(|preset| {
+            let label = if settings.preset.as_deref() == Some(preset.name) {
+                format!("✅ {} - {}", preset.name, preset.description)
+            } else {
+                format!("{} - {}", preset.name, preset.description)
+            };
+            vec![InlineKeyboardButton::callback(
+                label,
+                format!("{}{}", SETTINGS_CALLBACK_PREFIX, preset.name),
+            )]
+        })
+        .collect();
+    rows.push(vec![InlineKeyboardButton::callback(
+        "Reset to defaults",
+        format!("{}{}", SETTINGS_CALLBACK_PREFIX, RESET),
+    )]);
+
+    InlineKeyboardMarkup::new(rows)
+}
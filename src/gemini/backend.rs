@@ -0,0 +1,230 @@
+// The model behind the bot, so handlers don't depend on one HTTP endpoint
+use crate::app::config::AppConfig;
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{self, GeminiReply, GenerateOptions, HistoryTurn};
+use crate::models::gemini::{Content, Part};
+use crate::openai::{services as openai, OpenAiSettings};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// What the model is asked: the question with its attachments, extra instructions and the
+/// conversation before it.
+#[derive(Clone, Copy)]
+pub struct Prompt<'a> {
+    pub query: &'a str,
+    pub attachments: &'a [Part],
+    pub instructions: &'a Arc<Option<Vec<&'a str>>>,
+    pub history: &'a Arc<Option<Vec<HistoryTurn>>>,
+}
+
+/// Generates answers, implemented by the Gemini API and by the scripted backend of the tests.
+#[async_trait]
+pub trait GenerativeBackend: Send + Sync {
+    /// The complete answer to the prompt.
+    async fn generate(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+    ) -> Result<GeminiReply, GeminiError>;
+
+    /// Same as `generate`, sending the text generated so far through `updates` while it arrives.
+    async fn stream(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        updates: mpsc::UnboundedSender<String>,
+    ) -> Result<GeminiReply, GeminiError>;
+
+    /// Number of tokens the contents take for the model of the options.
+    async fn count_tokens(
+        &self,
+        config: &Arc<AppConfig>,
+        options: &GenerateOptions,
+        contents: Vec<Content>,
+    ) -> Result<i64, GeminiError>;
+}
+
+/// Handed to the handlers through `dptree::deps!`.
+pub type Backend = Arc<dyn GenerativeBackend>;
+
+/// The APIs a deployment can answer with, picked per deployment and per user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Gemini,
+    /// The OpenAI-compatible server of `AppConfig::openai`.
+    OpenAi,
+}
+
+impl BackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Gemini => "gemini",
+            BackendKind::OpenAi => "openai",
+        }
+    }
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "gemini" => Ok(BackendKind::Gemini),
+            "openai" => Ok(BackendKind::OpenAi),
+            _ => Err(format!("unknown backend {}", value)),
+        }
+    }
+}
+
+/// The Gemini API, configured by `AppConfig`.
+pub struct GeminiBackend;
+
+#[async_trait]
+impl GenerativeBackend for GeminiBackend {
+    async fn generate(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+    ) -> Result<GeminiReply, GeminiError> {
+        services::query_gemini_api(
+            prompt.query,
+            prompt.attachments,
+            prompt.instructions,
+            config,
+            prompt.history,
+            options,
+        )
+        .await
+    }
+
+    async fn stream(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        updates: mpsc::UnboundedSender<String>,
+    ) -> Result<GeminiReply, GeminiError> {
+        services::stream_gemini_api(
+            prompt.query,
+            prompt.attachments,
+            prompt.instructions,
+            config,
+            prompt.history,
+            options,
+            updates,
+        )
+        .await
+    }
+
+    async fn count_tokens(
+        &self,
+        config: &Arc<AppConfig>,
+        options: &GenerateOptions,
+        contents: Vec<Content>,
+    ) -> Result<i64, GeminiError> {
+        services::count_tokens(config, options, contents).await
+    }
+}
+
+/// A server speaking the OpenAI chat completions API.
+pub struct OpenAiBackend {
+    pub settings: OpenAiSettings,
+}
+
+#[async_trait]
+impl GenerativeBackend for OpenAiBackend {
+    async fn generate(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+    ) -> Result<GeminiReply, GeminiError> {
+        openai::query_openai_api(config, &self.settings, prompt, options).await
+    }
+
+    async fn stream(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        updates: mpsc::UnboundedSender<String>,
+    ) -> Result<GeminiReply, GeminiError> {
+        openai::stream_openai_api(config, &self.settings, prompt, options, updates).await
+    }
+
+    async fn count_tokens(
+        &self,
+        _config: &Arc<AppConfig>,
+        _options: &GenerateOptions,
+        contents: Vec<Content>,
+    ) -> Result<i64, GeminiError> {
+        Ok(openai::estimate_tokens(&contents))
+    }
+}
+
+/// The backends of the configuration, each request goes to the one the user picked
+/// or to the deployment default.
+pub struct ConfiguredBackend {
+    gemini: GeminiBackend,
+    openai: Option<OpenAiBackend>,
+}
+
+impl ConfiguredBackend {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            gemini: GeminiBackend,
+            openai: config
+                .openai
+                .clone()
+                .map(|settings| OpenAiBackend { settings }),
+        }
+    }
+
+    fn route(&self, config: &AppConfig, options: &GenerateOptions) -> &dyn GenerativeBackend {
+        match (config.backend(options.backend), &self.openai) {
+            (BackendKind::OpenAi, Some(openai)) => openai,
+            _ => &self.gemini,
+        }
+    }
+}
+
+#[async_trait]
+impl GenerativeBackend for ConfiguredBackend {
+    async fn generate(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+    ) -> Result<GeminiReply, GeminiError> {
+        self.route(config, options)
+            .generate(config, prompt, options)
+            .await
+    }
+
+    async fn stream(
+        &self,
+        config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        updates: mpsc::UnboundedSender<String>,
+    ) -> Result<GeminiReply, GeminiError> {
+        self.route(config, options)
+            .stream(config, prompt, options, updates)
+            .await
+    }
+
+    async fn count_tokens(
+        &self,
+        config: &Arc<AppConfig>,
+        options: &GenerateOptions,
+        contents: Vec<Content>,
+    ) -> Result<i64, GeminiError> {
+        self.route(config, options)
+            .count_tokens(config, options, contents)
+            .await
+    }
+}
@@ -0,0 +1,307 @@
+// Context caching, the stable start of a conversation is stored once as a cachedContents entry
+use crate::{
+    app::config,
+    gemini::{error::GeminiError, keys::API_KEY_HEADER, request::RequestBuilder},
+    models::{
+        gemini::{CachedContent, GenerateContentRequest, PartData},
+        gemini_cache::GeminiCache,
+    },
+    utils::time::unix_timestamp,
+};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Caches expiring sooner than this are not used anymore, so they don't vanish in the middle of a request.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// A cache holding the first contents of a request.
+#[derive(Debug, Clone)]
+pub struct CachedPrefix {
+    /// Resource name, e.g. `cachedContents/abc-123`.
+    pub name: String,
+    /// Caches only work with the model they were created for.
+    pub model: String,
+    prefix_len: usize,
+    fingerprint: String,
+}
+
+impl CachedPrefix {
+    /// The request referring to the cache instead of repeating its prefix, None when the request
+    /// does not start with the cached prefix anymore, e.g. because function calling was turned off.
+    pub fn apply(&self, request: &GenerateContentRequest) -> Option<GenerateContentRequest> {
+        if request.contents.len() <= self.prefix_len
+            || fingerprint(&self.model, request, self.prefix_len) != self.fingerprint
+        {
+            return None;
+        }
+
+        // The cache holds the system instruction and the tools, only the settings are repeated
+        let mut builder = RequestBuilder::new().cached_content(&self.name);
+        if let Some(generation_config) = &request.generation_config {
+            builder = builder.generation_config(generation_config.clone());
+        }
+        for setting in &request.safety_settings {
+            builder = builder.safety_setting(&setting.category, &setting.threshold);
+        }
+        let builder = request.contents[self.prefix_len..]
+            .iter()
+            .cloned()
+            .fold(builder, RequestBuilder::content);
+        Some(builder.build())
+    }
+}
+
+/// The cache of the stable start of the user's conversation, created when it is large enough
+/// and refreshed while the conversation goes on. The prefix is the system instruction, the tools
+/// and the history up to the last turn with attachments, e.g. an uploaded document.
+/// Failures are logged and the request is then sent in full.
+pub async fn prepare(
+    config: &Arc<config::AppConfig>,
+    user_id: i64,
+    model: &str,
+    request: &GenerateContentRequest,
+) -> Option<CachedPrefix> {
+    if !config.uses_context_cache() {
+        return None;
+    }
+
+    let prefix_len = stable_prefix_len(request);
+    if !worth_caching(config, request, prefix_len) {
+        return None;
+    }
+    let fingerprint = fingerprint(model, request, prefix_len);
+
+    let stored = {
+        let db = config.database.lock().await;
+        GeminiCache::find_by_user_id(user_id, &db)
+            .await
+            .unwrap_or_else(|err| {
+                log::error!("Error while loading cache: {:?}", err);
+                None
+            })
+    };
+    if let Some(mut stored) = stored {
+        let still_valid = stored.expires_at - EXPIRY_MARGIN_SECS > unix_timestamp();
+        if stored.fingerprint == fingerprint && stored.model == model && still_valid {
+            let name = stored.name.clone()?;
+            if stored.expires_at - unix_timestamp() < config.cache_ttl_secs / 2 {
+                match refresh(config, &name).await {
+                    Ok(expires_at) => {
+                        stored.expires_at = expires_at;
+                        let db = config.database.lock().await;
+                        if let Err(err) = stored.update_expires_at(&db).await {
+                            log::error!("Error while storing cache expiry: {:?}", err);
+                        }
+                    }
+                    Err(err) => log::warn!("Could not refresh cache {}: {}", name, err),
+                }
+            }
+            return Some(CachedPrefix {
+                name,
+                model: model.to_string(),
+                prefix_len,
+                fingerprint,
+            });
+        }
+
+        // The conversation moved on, e.g. its start was summarized
+        delete(config, user_id).await;
+    }
+
+    let created = create(config, model, request, prefix_len).await;
+    let cache = match &created {
+        Ok(content) => GeminiCache::new(
+            user_id,
+            content.name.clone(),
+            model.to_string(),
+            fingerprint.clone(),
+            prefix_len as i64,
+            expires_at(config, content),
+        ),
+        Err(err) => {
+            log::warn!("Could not cache the conversation of {}: {}", user_id, err);
+            GeminiCache::new(
+                user_id,
+                None,
+                model.to_string(),
+                fingerprint.clone(),
+                prefix_len as i64,
+                unix_timestamp() + config.cache_ttl_secs,
+            )
+        }
+    };
+    {
+        let db = config.database.lock().await;
+        if let Err(err) = cache.insert(&db).await {
+            log::error!("Error while storing cache: {:?}", err);
+        }
+    }
+
+    Some(CachedPrefix {
+        name: cache.name?,
+        model: model.to_string(),
+        prefix_len,
+        fingerprint,
+    })
+}
+
+/// Deletes the cache of the user's conversation, e.g. when the topic is flushed.
+pub async fn delete(config: &Arc<config::AppConfig>, user_id: i64) {
+    let stored = {
+        let db = config.database.lock().await;
+        match GeminiCache::find_by_user_id(user_id, &db).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Error while loading cache: {:?}", err);
+                return;
+            }
+        }
+    };
+
+    // Nothing is sent to AI Studio with Vertex AI, a cache left from before is only forgotten
+    let key = config
+        .gemini_keys
+        .primary()
+        .filter(|_| config.vertex.is_none());
+    if let (Some(name), Some(key)) = (&stored.name, key) {
+        let response = config
+            .http_client
+            .delete(format!("{}/{}", config.gemini_base_url, name))
+            .header(API_KEY_HEADER, key)
+            .send()
+            .await;
+        let result = match response {
+            Ok(response) => GeminiError::check_response(response).await.map(|_| ()),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            log::warn!("Could not delete cache {}: {}", name, err);
+        }
+    }
+    let db = config.database.lock().await;
+    if let Err(err) = GeminiCache::delete_by_user_id(user_id, &db).await {
+        log::error!("Error while deleting cache: {:?}", err);
+    }
+}
+
+/// Contents up to and including the answer to the last question with attachments,
+/// the question being asked is never part of it.
+fn stable_prefix_len(request: &GenerateContentRequest) -> usize {
+    let history = &request.contents[..request.contents.len().saturating_sub(1)];
+    history
+        .iter()
+        .rposition(|content| {
+            content
+                .parts
+                .iter()
+                .any(|x| !matches!(x.data, PartData::Text(_)))
+        })
+        .map_or(0, |i| (i + 2).min(history.len()))
+}
+
+/// Documents are always worth a try, otherwise the prefix has to reach the configured size.
+fn worth_caching(
+    config: &Arc<config::AppConfig>,
+    request: &GenerateContentRequest,
+    prefix_len: usize,
+) -> bool {
+    let prefix = &request.contents[..prefix_len];
+    if prefix
+        .iter()
+        .flat_map(|x| &x.parts)
+        .any(|x| matches!(x.data, PartData::FileData(_)))
+    {
+        return true;
+    }
+
+    let chars = serde_json::to_string(&(&request.system_instruction, &request.tools, prefix))
+        .map_or(0, |x| x.len());
+    chars as i64 / 4 >= config.cache_min_tokens
+}
+
+/// Identifies the model and everything in the prefix. The std hasher may change between
+/// releases, which only means the caches are created again.
+fn fingerprint(model: &str, request: &GenerateContentRequest, prefix_len: usize) -> String {
+    let prefix = serde_json::to_string(&(
+        &request.system_instruction,
+        &request.tools,
+        &request.tool_config,
+        &request.contents[..prefix_len],
+    ))
+    .unwrap_or_default();
+
+    let mut hasher = DefaultHasher::new();
+    model.hash(&mut hasher);
+    prefix.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+async fn create(
+    config: &Arc<config::AppConfig>,
+    model: &str,
+    request: &GenerateContentRequest,
+    prefix_len: usize,
+) -> Result<CachedContent, GeminiError> {
+    let content = CachedContent {
+        model: format!("models/{}", model),
+        contents: request.contents[..prefix_len].to_vec(),
+        system_instruction: request.system_instruction.clone(),
+        tools: request.tools.clone(),
+        tool_config: request.tool_config.clone(),
+        ttl: Some(format!("{}s", config.cache_ttl_secs)),
+        ..Default::default()
+    };
+
+    let key = config.gemini_keys.primary().ok_or(GeminiError::NoApiKey)?;
+    let response = config
+        .http_client
+        .post(format!("{}/cachedContents", config.gemini_base_url))
+        .header(API_KEY_HEADER, key)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&content).unwrap_or_default())
+        .send()
+        .await?;
+    let response = GeminiError::check_response(response).await?;
+
+    let content = serde_json::from_str::<CachedContent>(&response.text().await?)
+        .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+    match content.name {
+        Some(_) => Ok(content),
+        None => Err(GeminiError::MalformedBody(String::from(
+            "cached content has no name",
+        ))),
+    }
+}
+
+/// Extends the lifetime of the cache by the configured TTL, returns the new expiry.
+async fn refresh(config: &Arc<config::AppConfig>, name: &str) -> Result<i64, GeminiError> {
+    let key = config.gemini_keys.primary().ok_or(GeminiError::NoApiKey)?;
+    let response = config
+        .http_client
+        .patch(format!(
+            "{}/{}?updateMask=ttl",
+            config.gemini_base_url, name
+        ))
+        .header(API_KEY_HEADER, key)
+        .header("Content-Type", "application/json")
+        .body(json!({ "ttl": format!("{}s", config.cache_ttl_secs) }).to_string())
+        .send()
+        .await?;
+    let response = GeminiError::check_response(response).await?;
+
+    let content = serde_json::from_str::<CachedContent>(&response.text().await?)
+        .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+    Ok(expires_at(config, &content))
+}
+
+fn expires_at(config: &Arc<config::AppConfig>, content: &CachedContent) -> i64 {
+    content
+        .expire_time
+        .as_deref()
+        .and_then(|x| chrono::DateTime::parse_from_rfc3339(x).ok())
+        .map(|x| x.timestamp())
+        .unwrap_or(unix_timestamp() + config.cache_ttl_secs)
+}
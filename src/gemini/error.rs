@@ -0,0 +1,120 @@
+use crate::gemini::retry::parse_retry_delay;
+use crate::models::gemini::ApiErrorResponse;
+use std::{fmt, time::Duration};
+
+/// Everything that can go wrong while asking Gemini for a response.
+#[derive(Debug)]
+pub enum GeminiError {
+    /// The request could not be sent or the body could not be read.
+    Transport(reqwest::Error),
+    /// Gemini answered with a non-success status code.
+    HttpStatus { status: u16, message: String },
+    /// Quota or rate limit exceeded (HTTP 429), with the delay from `RetryInfo` if one was sent.
+    Quota {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The prompt or the generated answer was blocked by the safety filters, with the harm
+    /// category responsible when the ratings tell.
+    SafetyBlocked {
+        reason: String,
+        category: Option<String>,
+    },
+    /// Gemini answered without any candidate or text.
+    EmptyCandidates,
+    /// The body could not be parsed as a Gemini response.
+    MalformedBody(String),
+    /// A file uploaded through the Files API failed or took too long to process.
+    FileProcessing(String),
+    /// The request needs an AI Studio API key and none is configured, e.g. with only Vertex AI.
+    NoApiKey,
+    /// The Vertex AI service account key could not sign the token request.
+    Signing,
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeminiError::Transport(err) => write!(f, "transport error: {}", err),
+            GeminiError::HttpStatus { status, message } => {
+                write!(f, "gemini returned status {}: {}", status, message)
+            }
+            GeminiError::Quota { message, .. } => write!(f, "quota exceeded: {}", message),
+            GeminiError::SafetyBlocked { reason, category } => match category {
+                Some(category) => write!(f, "blocked by safety filters: {} ({})", reason, category),
+                None => write!(f, "blocked by safety filters: {}", reason),
+            },
+            GeminiError::EmptyCandidates => write!(f, "gemini returned no candidates"),
+            GeminiError::MalformedBody(err) => write!(f, "malformed response body: {}", err),
+            GeminiError::FileProcessing(name) => write!(f, "file {} could not be processed", name),
+            GeminiError::NoApiKey => write!(f, "no Gemini API key is configured"),
+            GeminiError::Signing => {
+                write!(f, "the service account could not sign the token request")
+            }
+        }
+    }
+}
+
+impl GeminiError {
+    /// Passes successful responses through and turns any other status into an error.
+    pub async fn check_response(
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, GeminiError> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let error = serde_json::from_str::<ApiErrorResponse>(&body).ok();
+        let retry_after = error
+            .as_ref()
+            .and_then(|x| x.error.retry_delay())
+            .and_then(parse_retry_delay);
+        let message = error.and_then(|x| x.error.message).unwrap_or(body);
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(GeminiError::Quota {
+                message,
+                retry_after,
+            })
+        } else {
+            Err(GeminiError::HttpStatus {
+                status: status.as_u16(),
+                message,
+            })
+        }
+    }
+
+    /// Whether the same request may succeed if it is sent again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GeminiError::Transport(_) | GeminiError::Quota { .. } => true,
+            GeminiError::HttpStatus { status, .. } => matches!(status, 500 | 502 | 503 | 504),
+            _ => false,
+        }
+    }
+
+    /// Delay requested by the server before trying again.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            GeminiError::Quota { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GeminiError::Transport(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GeminiError {
+    fn from(err: reqwest::Error) -> Self {
+        GeminiError::Transport(err)
+    }
+}
@@ -0,0 +1,170 @@
+// Files API, for media too large to be sent inline
+use crate::{
+    app::config,
+    gemini::{error::GeminiError, keys::API_KEY_HEADER},
+    models::{
+        gemini::{File, UploadFileResponse},
+        gemini_file::GeminiFile,
+    },
+    utils::time::unix_timestamp,
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+const FILE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How many times the file state is checked before giving up on processing.
+const FILE_POLL_ATTEMPTS: u32 = 120;
+/// Uploaded files are deleted by Gemini after 48 hours.
+const FILE_LIFETIME_SECS: i64 = 48 * 60 * 60;
+/// Files expiring sooner than this are uploaded again, so they do not vanish in the middle of a request.
+const EXPIRY_MARGIN_SECS: i64 = 10 * 60;
+
+/// The upload of the media, if it was uploaded before and is still usable.
+/// Expired uploads are forgotten, so the caller uploads the media again.
+pub async fn find_active(
+    config: &Arc<config::AppConfig>,
+    file_unique_id: &str,
+) -> Option<GeminiFile> {
+    let db = config.database.lock().await;
+    let file = match GeminiFile::find_by_file_unique_id(file_unique_id, &db).await {
+        Ok(file) => file?,
+        Err(err) => {
+            log::error!("Error while loading uploaded file: {:?}", err);
+            return None;
+        }
+    };
+
+    if file.expires_at - EXPIRY_MARGIN_SECS > unix_timestamp() {
+        return Some(file);
+    }
+
+    log::debug!(
+        "Upload of {} expired, it will be uploaded again",
+        file_unique_id
+    );
+    if let Err(err) = GeminiFile::delete_by_file_unique_id(file_unique_id, &db).await {
+        log::error!("Error while deleting expired file: {:?}", err);
+    }
+    None
+}
+
+/// Uploads the media and remembers the upload so it can be reused until it expires.
+pub async fn upload_and_track(
+    config: &Arc<config::AppConfig>,
+    file_unique_id: &str,
+    data: Vec<u8>,
+    mime_type: &str,
+) -> Result<GeminiFile, GeminiError> {
+    let file = upload_file(config, data, mime_type, file_unique_id).await?;
+    let file_uri = file.uri.ok_or(GeminiError::MalformedBody(format!(
+        "{} has no uri",
+        file.name
+    )))?;
+    let expires_at = file
+        .expiration_time
+        .as_deref()
+        .and_then(|x| chrono::DateTime::parse_from_rfc3339(x).ok())
+        .map(|x| x.timestamp())
+        .unwrap_or(unix_timestamp() + FILE_LIFETIME_SECS);
+
+    let file = GeminiFile::new(
+        file_unique_id.to_string(),
+        file.name,
+        file_uri,
+        mime_type.to_string(),
+        expires_at,
+    );
+
+    let db = config.database.lock().await;
+    if let Err(err) = GeminiFile::delete_expired(unix_timestamp(), &db).await {
+        log::error!("Error while deleting expired files: {:?}", err);
+    }
+    if let Err(err) = file.insert(&db).await {
+        log::error!("Error while storing uploaded file: {:?}", err);
+    }
+    Ok(file)
+}
+
+/// `https://host/v1beta` -> `https://host/upload/v1beta/files`
+fn upload_url(base_url: &str) -> String {
+    match base_url.trim_end_matches('/').rsplit_once('/') {
+        Some((host, version)) => format!("{}/upload/{}/files", host, version),
+        None => format!("{}/upload/files", base_url),
+    }
+}
+
+/// Uploads the file with the resumable upload protocol and waits until it can be used in requests.
+pub async fn upload_file(
+    config: &Arc<config::AppConfig>,
+    data: Vec<u8>,
+    mime_type: &str,
+    display_name: &str,
+) -> Result<File, GeminiError> {
+    let client = &config.http_client;
+    let key = config.gemini_keys.primary().ok_or(GeminiError::NoApiKey)?;
+
+    let response = client
+        .post(upload_url(&config.gemini_base_url))
+        .header(API_KEY_HEADER, &key)
+        .header("X-Goog-Upload-Protocol", "resumable")
+        .header("X-Goog-Upload-Command", "start")
+        .header("X-Goog-Upload-Header-Content-Length", data.len())
+        .header("X-Goog-Upload-Header-Content-Type", mime_type)
+        .header("Content-Type", "application/json")
+        .body(json!({ "file": { "display_name": display_name } }).to_string())
+        .send()
+        .await?;
+    let response = GeminiError::check_response(response).await?;
+
+    let session_url = response
+        .headers()
+        .get("x-goog-upload-url")
+        .and_then(|x| x.to_str().ok())
+        .ok_or(GeminiError::MalformedBody(String::from(
+            "upload session url is missing",
+        )))?
+        .to_string();
+
+    let response = client
+        .post(session_url)
+        .header("X-Goog-Upload-Offset", 0)
+        .header("X-Goog-Upload-Command", "upload, finalize")
+        .body(data)
+        .send()
+        .await?;
+    let response = GeminiError::check_response(response).await?;
+
+    let file = serde_json::from_str::<UploadFileResponse>(&response.text().await?)
+        .map_err(|err| GeminiError::MalformedBody(err.to_string()))?
+        .file;
+
+    wait_until_active(config, &key, file).await
+}
+
+/// Polls the file until it is `ACTIVE`, documents and videos need some processing after the upload.
+async fn wait_until_active(
+    config: &Arc<config::AppConfig>,
+    key: &str,
+    mut file: File,
+) -> Result<File, GeminiError> {
+    for _ in 0..FILE_POLL_ATTEMPTS {
+        match file.state.as_deref() {
+            Some("ACTIVE") | None => return Ok(file),
+            Some("FAILED") => return Err(GeminiError::FileProcessing(file.name)),
+            _ => tokio::time::sleep(FILE_POLL_INTERVAL).await,
+        }
+
+        let response = config
+            .http_client
+            .get(format!("{}/{}", config.gemini_base_url, file.name))
+            .header(API_KEY_HEADER, key)
+            .send()
+            .await?;
+        let response = GeminiError::check_response(response).await?;
+        file = serde_json::from_str::<File>(&response.text().await?)
+            .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+    }
+
+    Err(GeminiError::FileProcessing(file.name))
+}
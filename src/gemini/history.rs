@@ -0,0 +1,148 @@
+// Keeps the replayed conversation within a token budget by summarizing its oldest turns
+use crate::app::config::AppConfig;
+use crate::gemini::backend::{GenerativeBackend, Prompt};
+use crate::gemini::request::RequestBuilder;
+use crate::gemini::services::{GenerateOptions, HistoryTurn};
+use std::sync::Arc;
+
+/// Question the stored summary answers, so it is replayed like any other turn.
+pub const SUMMARY_QUERY: &str = "What did we talk about so far?";
+
+const SUMMARY_PROMPT: &str = "Summarize our conversation so far in a few short paragraphs. \
+Keep names, facts, decisions, open questions and what I told you about myself, leave out small talk. \
+Answer with the summary only, written from your point of view.";
+
+/// Tokens assumed for an attachment, what Gemini counts for a small image.
+const ATTACHMENT_TOKENS: i64 = 258;
+
+/// The history to replay and what changed to make it fit.
+pub struct FittedHistory {
+    /// Turns to replay, starting with the summary if there is one.
+    pub turns: Vec<HistoryTurn>,
+    /// Summary replacing the turns that were left out, None when nothing was summarized.
+    pub summary: Option<String>,
+    /// How many of the oldest raw turns the summary replaces.
+    pub summarized: usize,
+}
+
+/// Rough token count of a turn, about four characters per token.
+pub fn estimate_tokens(turn: &HistoryTurn) -> i64 {
+    let chars = turn.query.chars().count() + turn.response.chars().count();
+    (chars as i64 + 3) / 4 + turn.attachments.len() as i64 * ATTACHMENT_TOKENS
+}
+
+/// Fits `turns`, after the stored `summary`, into the configured history token budget.
+///
+/// The local estimate decides when a text only history is clearly small enough, otherwise
+/// `countTokens` gives the real size. Attachments can be far larger than estimated, so a history
+/// with any is always counted. When it goes over budget, the oldest turns are summarized together
+/// with the previous summary and only the newest turns filling up to half the budget are kept, so
+/// the next few messages don't need another summary.
+pub async fn fit_to_budget(
+    config: &Arc<AppConfig>,
+    backend: &dyn GenerativeBackend,
+    options: &GenerateOptions,
+    summary: Option<String>,
+    turns: Vec<HistoryTurn>,
+) -> FittedHistory {
+    let budget = config.history_token_budget;
+    let mut all: Vec<HistoryTurn> = summary
+        .map(|x| HistoryTurn::new(SUMMARY_QUERY.to_string(), x))
+        .into_iter()
+        .collect();
+    let has_summary = !all.is_empty();
+    all.extend(turns);
+
+    let mut estimates: Vec<i64> = all.iter().map(estimate_tokens).collect();
+    let estimate: i64 = estimates.iter().sum();
+    let attachments: usize = all.iter().map(|x| x.attachments.len()).sum();
+    if attachments == 0 && estimate <= budget / 2 {
+        return FittedHistory::unchanged(all);
+    }
+
+    let contents = RequestBuilder::new().history(&all).build().contents;
+    let total = match backend.count_tokens(config, options, contents).await {
+        Ok(total) => total,
+        Err(err) => {
+            log::warn!(
+                "Could not count history tokens, using the estimate: {}",
+                err
+            );
+            estimate
+        }
+    };
+    if total <= budget {
+        return FittedHistory::unchanged(all);
+    }
+
+    // Text is estimated well enough, what is missing is put on the attachments
+    if attachments > 0 && total > estimate {
+        let extra = (total - estimate) / attachments as i64;
+        for (tokens, turn) in estimates.iter_mut().zip(&all) {
+            *tokens += turn.attachments.len() as i64 * extra;
+        }
+    }
+    // Scale the estimates so they add up to the real count
+    let scale = total as f64 / estimates.iter().sum::<i64>().max(1) as f64;
+    let first_turn = usize::from(has_summary);
+    let mut kept_tokens = 0.0;
+    let mut keep_from = all.len();
+    while keep_from > first_turn {
+        let tokens = estimates[keep_from - 1] as f64 * scale;
+        if kept_tokens + tokens > (budget / 2) as f64 {
+            break;
+        }
+        kept_tokens += tokens;
+        keep_from -= 1;
+    }
+    let summarized = keep_from - first_turn;
+    if summarized == 0 {
+        return FittedHistory::unchanged(all);
+    }
+
+    let kept = all.split_off(keep_from);
+    let history = Arc::from(Some(all.clone()));
+    let summary_options = GenerateOptions {
+        model: options.model.clone(),
+        api_key: options.api_key.clone(),
+        backend: options.backend,
+        ..Default::default()
+    };
+    let prompt = Prompt {
+        query: SUMMARY_PROMPT,
+        attachments: &[],
+        instructions: &Arc::from(None),
+        history: &history,
+    };
+    match backend.generate(config, prompt, &summary_options).await {
+        Ok(reply) => {
+            let mut turns = vec![HistoryTurn::new(
+                SUMMARY_QUERY.to_string(),
+                reply.text.clone(),
+            )];
+            turns.extend(kept);
+            FittedHistory {
+                turns,
+                summary: Some(reply.text),
+                summarized,
+            }
+        }
+        Err(err) => {
+            // Leave the stored history alone and try again with the next message
+            log::warn!("Could not summarize the history: {}", err);
+            all.truncate(first_turn);
+            all.extend(kept);
+            FittedHistory::unchanged(all)
+        }
+    }
+}
+
+impl FittedHistory {
+    fn unchanged(turns: Vec<HistoryTurn>) -> Self {
+        Self {
+            turns,
+            summary: None,
+            summarized: 0,
+        }
+    }
+}
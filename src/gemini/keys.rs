@@ -0,0 +1,226 @@
+// Pool of Gemini API keys, spreading requests over several quotas
+use crate::app::config::AppConfig;
+use crate::gemini::error::GeminiError;
+use crate::models::gemini::UsageMetadata;
+use crate::utils::redact;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Header carrying the API key, keeping it out of URLs that may end up in logs and errors.
+pub const API_KEY_HEADER: &str = "x-goog-api-key";
+
+/// How the next key is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySelection {
+    /// Each key in turn.
+    RoundRobin,
+    /// The key that used the fewest tokens so far.
+    LeastUsed,
+}
+
+impl std::str::FromStr for KeySelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "round_robin" => Ok(KeySelection::RoundRobin),
+            "least_used" => Ok(KeySelection::LeastUsed),
+            _ => Err(format!("unknown key selection {}", value)),
+        }
+    }
+}
+
+/// Counters of one key since the bot started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    /// The last characters of the key, enough to tell keys apart in logs.
+    pub label: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub prompt_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+struct KeyState {
+    key: String,
+    usage: KeyUsage,
+    /// Quotas are per model, so a rate limited key can still be used for other models.
+    cooldowns: Vec<(String, Instant)>,
+}
+
+impl KeyState {
+    fn available(&self, model: &str, now: Instant) -> bool {
+        !self
+            .cooldowns
+            .iter()
+            .any(|(x, until)| x == model && *until > now)
+    }
+}
+
+struct PoolState {
+    keys: Vec<KeyState>,
+    /// Round robin position.
+    next: usize,
+}
+
+/// The configured API keys. Keys rate limited for a model are skipped until their cooldown ends.
+pub struct KeyPool {
+    state: Mutex<PoolState>,
+    selection: KeySelection,
+    /// Used when a 429 response does not say how long to wait.
+    default_cooldown: Duration,
+}
+
+impl KeyPool {
+    pub fn new(keys: Vec<String>, selection: KeySelection, default_cooldown: Duration) -> Self {
+        for key in &keys {
+            redact::register_secret(key);
+        }
+        let keys = keys
+            .into_iter()
+            .map(|key| KeyState {
+                usage: KeyUsage {
+                    label: label(&key),
+                    ..Default::default()
+                },
+                key,
+                cooldowns: Vec::new(),
+            })
+            .collect();
+
+        Self {
+            state: Mutex::new(PoolState { keys, next: 0 }),
+            selection,
+            default_cooldown,
+        }
+    }
+
+    /// The first key. Uploaded files and caches belong to the project of the key that created them,
+    /// so they are only created and used with this one. None when only Vertex AI is configured.
+    pub fn primary(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state.keys.first().map(|x| x.key.clone())
+    }
+
+    /// Picks a key for a request to `model` and counts the request.
+    /// When all of them are cooling down, the quota error says how long until one is available again.
+    pub fn acquire(&self, model: &str, primary_only: bool) -> Result<String, GeminiError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let candidates = candidates(&state, primary_only);
+        if candidates == 0 {
+            return Err(GeminiError::NoApiKey);
+        }
+
+        let index = match self.selection {
+            KeySelection::RoundRobin => (0..candidates)
+                .map(|offset| (state.next + offset) % candidates)
+                .find(|i| state.keys[*i].available(model, now)),
+            KeySelection::LeastUsed => (0..candidates)
+                .filter(|i| state.keys[*i].available(model, now))
+                .min_by_key(|i| {
+                    let usage = &state.keys[*i].usage;
+                    (usage.total_tokens, usage.requests)
+                }),
+        };
+
+        match index {
+            Some(index) => {
+                state.next = (index + 1) % state.keys.len();
+                let key = &mut state.keys[index];
+                key.usage.requests += 1;
+                Ok(key.key.clone())
+            }
+            None => Err(GeminiError::Quota {
+                message: format!("every API key is rate limited for {}", model),
+                retry_after: state.keys[..candidates]
+                    .iter()
+                    .flat_map(|x| &x.cooldowns)
+                    .filter(|(x, _)| x == model)
+                    .map(|(_, until)| until.saturating_duration_since(now))
+                    .min(),
+            }),
+        }
+    }
+
+    /// Whether a key is ready for `model` right now, never with an empty pool.
+    pub fn available(&self, model: &str, primary_only: bool) -> bool {
+        let state = self.state.lock().unwrap();
+        let candidates = candidates(&state, primary_only);
+        let now = Instant::now();
+        state.keys[..candidates]
+            .iter()
+            .any(|x| x.available(model, now))
+    }
+
+    /// Takes the key out of rotation for `model` after a 429, for as long as the response asked.
+    pub fn cool_down(&self, key: &str, model: &str, retry_after: Option<Duration>) {
+        let duration = retry_after.unwrap_or(self.default_cooldown);
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(state) = state.keys.iter_mut().find(|x| x.key == key) {
+            state.usage.rate_limited += 1;
+            state
+                .cooldowns
+                .retain(|(x, until)| x != model && *until > now);
+            state.cooldowns.push((model.to_string(), now + duration));
+            log::warn!(
+                "Key {} is rate limited for {}, cooling down for {:?} ({} requests, {} tokens so far)",
+                state.usage.label,
+                model,
+                duration,
+                state.usage.requests,
+                state.usage.total_tokens
+            );
+        }
+    }
+
+    /// Adds the tokens reported in `usageMetadata` to the key's counters.
+    pub fn record_usage(&self, key: &str, usage: &UsageMetadata) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(state) = state.keys.iter_mut().find(|x| x.key == key) {
+            let count = |x: Option<i64>| x.unwrap_or_default().max(0) as u64;
+            state.usage.prompt_tokens += count(usage.prompt_token_count);
+            state.usage.output_tokens += count(usage.candidates_token_count);
+            state.usage.total_tokens += count(usage.total_token_count);
+        }
+    }
+
+    /// Counters of every key, in configuration order.
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        let state = self.state.lock().unwrap();
+        state.keys.iter().map(|x| x.usage.clone()).collect()
+    }
+}
+
+/// Number of keys a request may use, counted from the primary one.
+fn candidates(state: &PoolState, primary_only: bool) -> usize {
+    if primary_only {
+        state.keys.len().min(1)
+    } else {
+        state.keys.len()
+    }
+}
+
+/// Checks that Gemini accepts the key by listing a single model, which costs no quota.
+pub async fn validate_key(config: &Arc<AppConfig>, key: &str) -> Result<(), GeminiError> {
+    let response = config
+        .http_client
+        .get(format!("{}/models?pageSize=1", config.gemini_base_url))
+        .header(API_KEY_HEADER, key)
+        .send()
+        .await?;
+    GeminiError::check_response(response).await.map(|_| ())
+}
+
+/// `...wxyz`, never more than the last four characters of the key, nothing of keys too short
+/// to keep most of them hidden.
+pub fn label(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return String::from("...");
+    }
+    let tail: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+    format!("...{}", tail)
+}
@@ -1 +1,12 @@
-pub mod services;
\ No newline at end of file
+pub mod backend;
+pub mod cache;
+pub mod error;
+pub mod files;
+pub mod history;
+pub mod keys;
+pub mod request;
+pub mod retry;
+pub mod safety;
+pub mod services;
+pub mod sse;
+pub mod vertex;
@@ -0,0 +1,94 @@
+// Typed builder for generateContent requests
+use crate::gemini::services::HistoryTurn;
+use crate::models::gemini::{
+    Content, FunctionCallingConfig, GenerateContentRequest, GenerationConfig, Part, SafetySetting,
+    Tool, ToolConfig,
+};
+
+/// Composes a `GenerateContentRequest` step by step, e.g.
+/// `RequestBuilder::new().system_instruction("Be brief").user("Hi", &[]).build()`.
+#[derive(Debug, Default)]
+pub struct RequestBuilder {
+    request: GenerateContentRequest,
+}
+
+impl RequestBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn system_instruction(mut self, text: &str) -> Self {
+        self.request.system_instruction = Some(Content {
+            parts: vec![Part::text(text)],
+            role: None,
+        });
+        self
+    }
+
+    /// Replays earlier questions and answers before the current one.
+    pub fn history(mut self, history: &[HistoryTurn]) -> Self {
+        for turn in history {
+            self = self
+                .user(&turn.query, &turn.attachments)
+                .content(Content::model(vec![Part::text(&turn.response)]));
+        }
+        self
+    }
+
+    /// Adds a user turn, the attachments come before the text so the question can refer to them.
+    pub fn user(self, text: &str, attachments: &[Part]) -> Self {
+        let mut parts = attachments.to_vec();
+        parts.push(Part::text(text));
+        self.content(Content::user(parts))
+    }
+
+    pub fn content(mut self, content: Content) -> Self {
+        self.request.contents.push(content);
+        self
+    }
+
+    pub fn generation_config(mut self, generation_config: GenerationConfig) -> Self {
+        self.request.generation_config = Some(generation_config);
+        self
+    }
+
+    pub fn safety_setting(mut self, category: &str, threshold: &str) -> Self {
+        self.request.safety_settings.push(SafetySetting {
+            category: category.to_string(),
+            threshold: threshold.to_string(),
+        });
+        self
+    }
+
+    pub fn tool(mut self, tool: Tool) -> Self {
+        self.request.tools.push(tool);
+        self
+    }
+
+    /// `AUTO`, `ANY` or `NONE`.
+    pub fn function_calling_mode(mut self, mode: &str) -> Self {
+        self.request.tool_config = Some(ToolConfig {
+            function_calling_config: FunctionCallingConfig {
+                mode: mode.to_string(),
+                allowed_function_names: Vec::new(),
+            },
+        });
+        self
+    }
+
+    pub fn cached_content(mut self, name: &str) -> Self {
+        self.request.cached_content = Some(name.to_string());
+        self
+    }
+
+    pub fn build(self) -> GenerateContentRequest {
+        self.request
+    }
+}
+
+/// Continues an existing request, e.g. to change it between the rounds of function calling.
+impl From<GenerateContentRequest> for RequestBuilder {
+    fn from(request: GenerateContentRequest) -> Self {
+        Self { request }
+    }
+}
@@ -0,0 +1,45 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How often and how long to wait before a failed Gemini request is tried again.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries per model, the first attempt is not counted.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (starting at 0), using exponential backoff with full jitter.
+    /// A delay requested by the server takes precedence.
+    /// Returns None when the requested delay is longer than `max_delay`, so the caller can move on.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let millis = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64);
+        Some(Duration::from_millis(millis))
+    }
+}
+
+/// Parses protobuf duration strings such as "34s" or "0.5s" used by `google.rpc.RetryInfo`.
+pub fn parse_retry_delay(delay: &str) -> Option<Duration> {
+    let seconds = delay.trim().strip_suffix('s')?.parse::<f64>().ok()?;
+    (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
@@ -0,0 +1,76 @@
+// Harm categories and block thresholds of the safetySettings
+use crate::models::gemini::SafetyRating;
+
+/// Short names users type, with the harm category they stand for.
+pub const CATEGORIES: &[(&str, &str)] = &[
+    ("harassment", "HARM_CATEGORY_HARASSMENT"),
+    ("hate", "HARM_CATEGORY_HATE_SPEECH"),
+    ("sexual", "HARM_CATEGORY_SEXUALLY_EXPLICIT"),
+    ("dangerous", "HARM_CATEGORY_DANGEROUS_CONTENT"),
+    ("civic", "HARM_CATEGORY_CIVIC_INTEGRITY"),
+];
+
+/// Short names of the block thresholds, from the strictest to the loosest.
+pub const THRESHOLDS: &[(&str, &str)] = &[
+    ("low", "BLOCK_LOW_AND_ABOVE"),
+    ("medium", "BLOCK_MEDIUM_AND_ABOVE"),
+    ("high", "BLOCK_ONLY_HIGH"),
+    ("none", "BLOCK_NONE"),
+    ("off", "OFF"),
+];
+
+/// Harm category for a short name such as `hate`, the API name is accepted as well.
+pub fn parse_category(name: &str) -> Option<&'static str> {
+    find(CATEGORIES, name)
+}
+
+/// Threshold for a short name such as `high`, the API name is accepted as well.
+pub fn parse_threshold(name: &str) -> Option<&'static str> {
+    find(THRESHOLDS, name)
+}
+
+fn find(table: &[(&'static str, &'static str)], name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    table
+        .iter()
+        .find(|(short, api)| short.eq_ignore_ascii_case(name) || api.eq_ignore_ascii_case(name))
+        .map(|(_, api)| *api)
+}
+
+/// Short name of a category or threshold, unknown API names are shown as they are.
+pub fn short_name(api_name: &str) -> &str {
+    CATEGORIES
+        .iter()
+        .chain(THRESHOLDS)
+        .find(|(_, api)| *api == api_name)
+        .map_or(api_name, |(short, _)| short)
+}
+
+/// Whether `threshold` blocks at least as much as `limit`.
+pub fn within_limit(threshold: &str, limit: &str) -> bool {
+    let position = |x: &str| THRESHOLDS.iter().position(|(_, api)| *api == x);
+    match (position(threshold), position(limit)) {
+        (Some(threshold), Some(limit)) => threshold <= limit,
+        _ => false,
+    }
+}
+
+/// Category that got the content blocked, the one flagged as `blocked` or else the most
+/// probable one.
+pub fn blocking_category(ratings: &[SafetyRating]) -> Option<String> {
+    const PROBABILITIES: [&str; 3] = ["LOW", "MEDIUM", "HIGH"];
+
+    if let Some(rating) = ratings.iter().find(|x| x.blocked) {
+        return Some(rating.category.clone());
+    }
+    ratings
+        .iter()
+        .filter_map(|x| {
+            PROBABILITIES
+                .iter()
+                .position(|p| *p == x.probability)
+                .map(|p| (p, x))
+        })
+        .max_by_key(|(p, _)| *p)
+        .map(|(_, x)| x.category.clone())
+}
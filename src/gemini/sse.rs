@@ -0,0 +1,51 @@
+// Server-sent events parsing for streamGenerateContent
+pub struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// Feeds a raw body chunk and returns the `data` payload of every event completed by it.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend(chunk.iter().filter(|b| **b != b'\r'));
+
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..pos + 2).collect();
+            if let Some(data) = Self::parse_event(&event) {
+                events.push(data);
+            }
+        }
+        events
+    }
+
+    /// Returns the last event if the stream ended without a trailing blank line.
+    pub fn finish(&mut self) -> Option<String> {
+        let event = std::mem::take(&mut self.buffer);
+        Self::parse_event(&event)
+    }
+
+    fn parse_event(event: &[u8]) -> Option<String> {
+        let event = String::from_utf8_lossy(event);
+        let data: Vec<&str> = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| data.strip_prefix(' ').unwrap_or(data))
+            .collect();
+
+        if data.is_empty() {
+            None
+        } else {
+            Some(data.join("\n"))
+        }
+    }
+}
+
+impl Default for SseParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
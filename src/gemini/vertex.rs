@@ -0,0 +1,180 @@
+// Vertex AI, Gemini through a Google Cloud project authorized with a service account
+use crate::gemini::error::GeminiError;
+use crate::utils::time::unix_timestamp;
+use base64::prelude::*;
+use ring::rand::SystemRandom;
+use ring::signature::{self, RsaKeyPair};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+pub const DEFAULT_VERTEX_LOCATION: &str = "us-central1";
+
+/// Token endpoint of service accounts whose key file doesn't name one.
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+/// Longest lifetime Google accepts for the signed assertion.
+const ASSERTION_LIFETIME_SECS: i64 = 3600;
+/// Tokens are renewed this long before they expire, so none expires in the middle of a request.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// The fields of a service account key file the bot needs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccount {
+    pub client_email: String,
+    /// PKCS#8 RSA key in PEM format.
+    pub private_key: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+    pub project_id: Option<String>,
+}
+
+fn default_token_uri() -> String {
+    String::from(DEFAULT_TOKEN_URI)
+}
+
+impl ServiceAccount {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|err| format!("invalid service account file: {}", err))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: i64,
+}
+
+/// Requests to Vertex AI: regional URLs of the project and OAuth tokens of the service account,
+/// exchanged for a signed JWT and reused until shortly before they expire.
+pub struct Vertex {
+    account: ServiceAccount,
+    key_pair: RsaKeyPair,
+    pub project: String,
+    pub location: String,
+    /// Up to and including the version, e.g. `https://us-central1-aiplatform.googleapis.com/v1`.
+    pub base_url: String,
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl Vertex {
+    /// The project defaults to the one of the service account.
+    pub fn new(
+        account: ServiceAccount,
+        project: Option<String>,
+        location: &str,
+    ) -> Result<Self, String> {
+        let key_pair = RsaKeyPair::from_pkcs8(&pem_to_der(&account.private_key)?)
+            .map_err(|err| format!("invalid service account key: {}", err))?;
+        let project = project
+            .or_else(|| account.project_id.clone())
+            .ok_or("no project given and the service account names none")?;
+
+        Ok(Self {
+            account,
+            key_pair,
+            project,
+            location: location.to_string(),
+            base_url: endpoint(location),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// URL of `method`, e.g. `generateContent`, of a Gemini model in the project's location.
+    pub fn model_url(&self, model: &str, method: &str) -> String {
+        format!(
+            "{}/projects/{}/locations/{}/publishers/google/models/{}:{}",
+            self.base_url, self.project, self.location, model, method
+        )
+    }
+
+    /// A valid access token, a new one is only requested when the cached one is about to expire.
+    /// The lock is held while requesting it, so concurrent requests wait for the same token.
+    pub async fn access_token(&self, client: &reqwest::Client) -> Result<String, GeminiError> {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if token.expires_at - EXPIRY_MARGIN_SECS > unix_timestamp() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let now = unix_timestamp();
+        let assertion = self.assertion(now)?;
+        let response = client
+            .post(&self.account.token_uri)
+            .form(&[("grant_type", GRANT_TYPE), ("assertion", &assertion)])
+            .send()
+            .await?;
+        let response = GeminiError::check_response(response).await?;
+        let token = serde_json::from_str::<TokenResponse>(&response.text().await?)
+            .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+
+        log::info!(
+            "Got a Vertex AI access token for {}, valid for {}s",
+            self.account.client_email,
+            token.expires_in
+        );
+        *cached = Some(AccessToken {
+            token: token.access_token.clone(),
+            expires_at: now + token.expires_in,
+        });
+        Ok(token.access_token)
+    }
+
+    /// The JWT proving the request comes from the service account, signed with RS256.
+    fn assertion(&self, now: i64) -> Result<String, GeminiError> {
+        let header = json!({ "alg": "RS256", "typ": "JWT" });
+        let claims = json!({
+            "iss": self.account.client_email,
+            "scope": SCOPE,
+            "aud": self.account.token_uri,
+            "iat": now,
+            "exp": now + ASSERTION_LIFETIME_SECS,
+        });
+        let message = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(header.to_string()),
+            BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+
+        let mut signature = vec![0; self.key_pair.public().modulus_len()];
+        self.key_pair
+            .sign(
+                &signature::RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| GeminiError::Signing)?;
+        Ok(format!(
+            "{}.{}",
+            message,
+            BASE64_URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+/// Regional endpoint of the location, `global` has no region in its host.
+pub fn endpoint(location: &str) -> String {
+    match location {
+        "global" => String::from("https://aiplatform.googleapis.com/v1"),
+        _ => format!("https://{}-aiplatform.googleapis.com/v1", location),
+    }
+}
+
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with("-----"))
+        .collect();
+    BASE64_STANDARD
+        .decode(body)
+        .map_err(|err| format!("invalid service account key: {}", err))
+}
@@ -6,6 +6,7 @@ use log::*;
 use teloxide::prelude::*;
 use tokio::sync::Mutex;
 use app::config::AppConfig;
+use gemini::backend::{Backend, ConfiguredBackend};
 
 #[cfg(test)]
 mod tests;
@@ -14,12 +15,14 @@ mod gemini;
 mod app;
 mod db;
 mod models;
+mod openai;
+mod tools;
 mod utils;
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().expect("Failed to load .env variables!");
-    pretty_env_logger::init();
+    utils::redact::init_logger();
     info!("Initializing bot!");
 
     let database: Option<Database> = match Database::new("sqlite:./database.db").await {
@@ -35,6 +38,15 @@ async fn main() {
     let config = Arc::new(AppConfig::new(database.unwrap()));
     let user_states: UserStates = Arc::new(Mutex::new(HashMap::new()));
     let bot = Bot::from_env();
-    let mut dispatcher = bot_logic::setup_dispatcher(bot, config, user_states).await;
+    let backend: Backend = Arc::new(ConfiguredBackend::new(&config));
+    let mut dispatcher = bot_logic::setup_dispatcher(bot, config.clone(), backend, user_states).await;
     dispatcher.dispatch().await;
+
+    // The counters only live as long as the process
+    for usage in config.gemini_keys.usage() {
+        info!(
+            "Key {}: {} requests, {} rate limited, {} prompt and {} output tokens",
+            usage.label, usage.requests, usage.rate_limited, usage.prompt_tokens, usage.output_tokens
+        );
+    }
 }
\ No newline at end of file
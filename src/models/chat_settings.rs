@@ -0,0 +1,75 @@
+use crate::db::database::Database;
+use crate::models::gemini::SafetySetting;
+use serde::{Deserialize, Serialize};
+use sqlx::{self, Row};
+
+/// Settings shared by everyone in a chat, changed by group admins or the user of a private chat.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatSettings {
+    pub id: i64,
+    pub chat_id: i64,
+    /// Thresholds sent as `safetySettings`, categories not listed use the API defaults.
+    pub safety_settings: Vec<SafetySetting>,
+}
+
+#[allow(dead_code)]
+impl ChatSettings {
+    pub fn new(chat_id: i64) -> Self {
+        ChatSettings {
+            id: 0,
+            chat_id,
+            safety_settings: Vec::new(),
+        }
+    }
+
+    pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO chat_settings (chat_id, safety_settings) VALUES (?, ?)",
+        )
+        .bind(self.chat_id)
+        .bind(serde_json::to_string(&self.safety_settings).unwrap())
+        .execute(db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_chat_id(
+        chat_id: i64,
+        db: &Database,
+    ) -> Result<Option<ChatSettings>, sqlx::Error> {
+        let row =
+            sqlx::query("SELECT id, chat_id, safety_settings FROM chat_settings WHERE chat_id = ?")
+                .bind(chat_id)
+                .fetch_optional(db.pool())
+                .await?;
+
+        match row {
+            Some(row) => {
+                let safety_settings: String = row.try_get("safety_settings")?;
+                let settings = ChatSettings {
+                    id: row.try_get("id")?,
+                    chat_id: row.try_get("chat_id")?,
+                    safety_settings: serde_json::from_str(&safety_settings).unwrap_or_default(),
+                };
+                Ok(Some(settings))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Settings of the chat, or the defaults if nobody changed anything.
+    pub async fn find_or_default(chat_id: i64, db: &Database) -> Result<ChatSettings, sqlx::Error> {
+        Ok(Self::find_by_chat_id(chat_id, db)
+            .await?
+            .unwrap_or_else(|| Self::new(chat_id)))
+    }
+
+    pub async fn delete_by_chat_id(chat_id: i64, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM chat_settings WHERE chat_id = ?")
+            .bind(chat_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+}
@@ -1,8 +1,141 @@
+use base64::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// Body of `generateContent`, `streamGenerateContent` and `countTokens`.
+/// Built with `gemini::request::RequestBuilder`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerateContentRequest {
+    pub contents: Vec<Content>,
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+    #[serde(
+        rename = "safetySettings",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub safety_settings: Vec<SafetySetting>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// Name of a `cachedContents` resource holding the start of the conversation.
+    #[serde(rename = "cachedContent", skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(rename = "topK", skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(rename = "candidateCount", skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(rename = "maxOutputTokens", skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(
+        rename = "stopSequences",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub stop_sequences: Vec<String>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(rename = "thinkingConfig", skip_serializing_if = "Option::is_none")]
+    pub thinking_config: Option<ThinkingConfig>,
+}
+
+/// Only understood by thinking models.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThinkingConfig {
+    /// Tokens the model may spend thinking, 0 turns thinking off and -1 lets the model decide.
+    #[serde(rename = "thinkingBudget", skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SafetySetting {
+    /// e.g. `HARM_CATEGORY_HARASSMENT`.
+    pub category: String,
+    /// e.g. `BLOCK_MEDIUM_AND_ABOVE`.
+    pub threshold: String,
+}
+
+/// Tools the model may use, each entry sets one kind.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(
+        rename = "functionDeclarations",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub function_declarations: Vec<FunctionDeclaration>,
+    #[serde(rename = "googleSearch", skip_serializing_if = "Option::is_none")]
+    pub google_search: Option<GoogleSearch>,
+    #[serde(rename = "codeExecution", skip_serializing_if = "Option::is_none")]
+    pub code_execution: Option<CodeExecution>,
+}
+
+impl Tool {
+    pub fn functions(function_declarations: Vec<FunctionDeclaration>) -> Self {
+        Tool {
+            function_declarations,
+            ..Default::default()
+        }
+    }
+
+    pub fn google_search() -> Self {
+        Tool {
+            google_search: Some(GoogleSearch {}),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    /// JSON schema (OpenAPI subset) of the arguments.
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoogleSearch {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeExecution {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolConfig {
+    #[serde(rename = "functionCallingConfig")]
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallingConfig {
+    /// `AUTO`, `ANY` or `NONE`.
+    pub mode: String,
+    #[serde(
+        rename = "allowedFunctionNames",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub allowed_function_names: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GeminiResponse {
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
+    #[serde(rename = "promptFeedback")]
+    pub prompt_feedback: Option<PromptFeedback>,
     #[serde(rename = "usageMetadata")]
     pub usage_metadata: Option<UsageMetadata>,
     #[serde(rename = "modelVersion")]
@@ -11,24 +144,181 @@ pub struct GeminiResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Candidate {
+    #[serde(default)]
     pub content: Content,
     #[serde(rename = "finishReason")]
     pub finish_reason: Option<String>,
     #[serde(rename = "citationMetadata")]
     pub citation_metadata: Option<CitationMetadata>,
+    #[serde(rename = "groundingMetadata")]
+    pub grounding_metadata: Option<GroundingMetadata>,
     #[serde(rename = "avgLogprobs")]
     pub avg_logprobs: Option<f64>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<SafetyRating>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Content {
+    #[serde(default)]
     pub parts: Vec<Part>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Content {
+    pub fn user(parts: Vec<Part>) -> Self {
+        Content {
+            parts,
+            role: Some(String::from("user")),
+        }
+    }
+
+    pub fn model(parts: Vec<Part>) -> Self {
+        Content {
+            parts,
+            role: Some(String::from("model")),
+        }
+    }
+}
+
+/// One piece of a `Content`. What it holds is in `data`, the other fields are metadata
+/// any kind of part can carry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Part {
-    pub text: String,
+    #[serde(flatten)]
+    pub data: PartData,
+    /// The text is reasoning of a thinking model, not part of the answer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub thought: bool,
+    /// Opaque signature some models attach to their parts, it has to be sent back unchanged.
+    #[serde(rename = "thoughtSignature", skip_serializing_if = "Option::is_none")]
+    pub thought_signature: Option<String>,
+}
+
+/// Every documented kind of part, serialized as `{"<kind>": ...}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PartData {
+    Text(String),
+    InlineData(InlineData),
+    FileData(FileData),
+    FunctionCall(FunctionCall),
+    FunctionResponse(FunctionResponse),
+    ExecutableCode(ExecutableCode),
+    CodeExecutionResult(CodeExecutionResult),
+}
+
+impl Part {
+    pub fn new(data: PartData) -> Self {
+        Part {
+            data,
+            thought: false,
+            thought_signature: None,
+        }
+    }
+
+    pub fn text(text: &str) -> Self {
+        Self::new(PartData::Text(text.to_string()))
+    }
+
+    /// Media sent inline as base64, limited to 20MB per request.
+    pub fn inline_data(mime_type: &str, data: &[u8]) -> Self {
+        Self::new(PartData::InlineData(InlineData {
+            mime_type: mime_type.to_string(),
+            data: BASE64_STANDARD.encode(data),
+        }))
+    }
+
+    /// Media uploaded through the Files API.
+    pub fn file_data(mime_type: &str, file_uri: &str) -> Self {
+        Self::new(PartData::FileData(FileData {
+            mime_type: mime_type.to_string(),
+            file_uri: file_uri.to_string(),
+        }))
+    }
+
+    /// The result of a function the model asked to call.
+    pub fn function_response(name: &str, response: serde_json::Value) -> Self {
+        Self::new(PartData::FunctionResponse(FunctionResponse {
+            name: name.to_string(),
+            response,
+        }))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InlineData {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    /// Base64 encoded bytes.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileData {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    #[serde(rename = "fileUri")]
+    pub file_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
+/// Code generated by the code execution tool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutableCode {
+    /// e.g. `PYTHON`.
+    pub language: String,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeExecutionResult {
+    /// `OUTCOME_OK`, `OUTCOME_FAILED` or `OUTCOME_DEADLINE_EXCEEDED`.
+    pub outcome: String,
+    #[serde(default)]
+    pub output: String,
+}
+
+// The payload can be several megabytes, only its size is worth printing
+impl std::fmt::Debug for InlineData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InlineData")
+            .field("mime_type", &self.mime_type)
+            .field("data", &format_args!("<{} base64 chars>", self.data.len()))
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    pub block_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyRating {
+    /// e.g. `HARM_CATEGORY_HARASSMENT`.
+    pub category: String,
+    /// `NEGLIGIBLE`, `LOW`, `MEDIUM` or `HIGH`.
+    pub probability: String,
+    /// Set when this category is the reason the content was blocked.
+    #[serde(default)]
+    pub blocked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,7 +336,57 @@ pub struct CitationSource {
     pub uri: Option<String>,
 }
 
+/// Search results the answer is based on, present when the `google_search` tool was used.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GroundingMetadata {
+    #[serde(rename = "webSearchQueries", default)]
+    pub web_search_queries: Vec<String>,
+    #[serde(rename = "searchEntryPoint")]
+    pub search_entry_point: Option<SearchEntryPoint>,
+    #[serde(rename = "groundingChunks", default)]
+    pub grounding_chunks: Vec<GroundingChunk>,
+    #[serde(rename = "groundingSupports", default)]
+    pub grounding_supports: Vec<GroundingSupport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchEntryPoint {
+    /// HTML and CSS of the Google Search suggestions chip.
+    #[serde(rename = "renderedContent")]
+    pub rendered_content: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct GroundingChunk {
+    pub web: Option<WebSource>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebSource {
+    pub uri: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Links a part of the answer to the chunks supporting it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroundingSupport {
+    pub segment: Option<Segment>,
+    #[serde(rename = "groundingChunkIndices", default)]
+    pub grounding_chunk_indices: Vec<usize>,
+    #[serde(rename = "confidenceScores", default)]
+    pub confidence_scores: Vec<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Segment {
+    #[serde(rename = "startIndex")]
+    pub start_index: Option<i64>,
+    #[serde(rename = "endIndex")]
+    pub end_index: Option<i64>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsageMetadata {
     #[serde(rename = "promptTokenCount")]
     pub prompt_token_count: Option<i64>,
@@ -60,9 +400,90 @@ pub struct UsageMetadata {
     pub candidates_tokens_details: Option<Vec<TokenDetails>>,
 }
 
+/// Response of `countTokens`.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct CountTokensResponse {
+    #[serde(rename = "totalTokens", default)]
+    pub total_tokens: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenDetails {
     pub modality: Option<String>,
     #[serde(rename = "tokenCount")]
     pub token_count: Option<i64>,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiErrorResponse {
+    pub error: ApiError,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: Option<i64>,
+    pub message: Option<String>,
+    pub status: Option<String>,
+    #[serde(default)]
+    pub details: Vec<serde_json::Value>,
+}
+
+impl ApiError {
+    /// `retryDelay` of the `google.rpc.RetryInfo` entry in `details`, if there is one.
+    pub fn retry_delay(&self) -> Option<&str> {
+        self.details
+            .iter()
+            .find(|x| {
+                x.get("@type").and_then(|t| t.as_str())
+                    == Some("type.googleapis.com/google.rpc.RetryInfo")
+            })
+            .and_then(|x| x.get("retryDelay"))
+            .and_then(|x| x.as_str())
+    }
+}
+
+/// A `cachedContents` entry, the request body when creating one and the resource returned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CachedContent {
+    /// Resource name, e.g. `cachedContents/abc-123`, set by the API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// e.g. `models/gemini-2.0-flash`.
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<Content>,
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// Lifetime such as `3600s`, only sent when creating or refreshing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    #[serde(rename = "expireTime", skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<String>,
+}
+
+/// A file uploaded through the Files API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct File {
+    /// Resource name, e.g. `files/abc-123`.
+    pub name: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    #[serde(rename = "mimeType")]
+    pub mime_type: Option<String>,
+    #[serde(rename = "sizeBytes")]
+    pub size_bytes: Option<String>,
+    #[serde(rename = "expirationTime")]
+    pub expiration_time: Option<String>,
+    pub uri: Option<String>,
+    /// `PROCESSING`, `ACTIVE` or `FAILED`.
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadFileResponse {
+    pub file: File,
+}
@@ -0,0 +1,102 @@
+use crate::db::database::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::{self, Row};
+
+/// The `cachedContents` entry holding the start of a user's conversation.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeminiCache {
+    pub id: i64,
+    pub user_id: i64,
+    /// Resource name, e.g. `cachedContents/abc-123`. None when the prefix could not be cached,
+    /// so it is not tried again on every message.
+    pub name: Option<String>,
+    pub model: String,
+    /// Identifies the cached prefix, it no longer matches once the prefix changes.
+    pub fingerprint: String,
+    /// How many contents of the conversation the cache holds.
+    pub prefix_len: i64,
+    pub expires_at: i64, // Unix timestamp
+}
+
+#[allow(dead_code)]
+impl GeminiCache {
+    pub fn new(
+        user_id: i64,
+        name: Option<String>,
+        model: String,
+        fingerprint: String,
+        prefix_len: i64,
+        expires_at: i64,
+    ) -> Self {
+        GeminiCache {
+            id: 0,
+            user_id,
+            name,
+            model,
+            fingerprint,
+            prefix_len,
+            expires_at,
+        }
+    }
+
+    pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO gemini_caches (user_id, name, model, fingerprint, prefix_len, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.user_id)
+        .bind(&self.name)
+        .bind(&self.model)
+        .bind(&self.fingerprint)
+        .bind(self.prefix_len)
+        .bind(self.expires_at)
+        .execute(db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_user_id(
+        user_id: i64,
+        db: &Database,
+    ) -> Result<Option<GeminiCache>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT id, user_id, name, model, fingerprint, prefix_len, expires_at FROM gemini_caches WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?;
+
+        match row {
+            Some(row) => {
+                let cache = GeminiCache {
+                    id: row.try_get("id")?,
+                    user_id: row.try_get("user_id")?,
+                    name: row.try_get("name")?,
+                    model: row.try_get("model")?,
+                    fingerprint: row.try_get("fingerprint")?,
+                    prefix_len: row.try_get("prefix_len")?,
+                    expires_at: row.try_get("expires_at")?,
+                };
+                Ok(Some(cache))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn update_expires_at(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE gemini_caches SET expires_at = ? WHERE user_id = ?")
+            .bind(self.expires_at)
+            .bind(self.user_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_by_user_id(user_id: i64, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM gemini_caches WHERE user_id = ?")
+            .bind(user_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+}
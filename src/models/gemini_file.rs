@@ -0,0 +1,97 @@
+use crate::db::database::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::{self, Row};
+
+/// A file uploaded through the Files API, keyed by the telegram `file_unique_id` of the media.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeminiFile {
+    pub id: i64,
+    pub file_unique_id: String,
+    /// Resource name, e.g. `files/abc-123`.
+    pub name: String,
+    pub file_uri: String,
+    pub mime_type: String,
+    pub expires_at: i64, // Unix timestamp
+}
+
+#[allow(dead_code)]
+impl GeminiFile {
+    pub fn new(
+        file_unique_id: String,
+        name: String,
+        file_uri: String,
+        mime_type: String,
+        expires_at: i64,
+    ) -> Self {
+        GeminiFile {
+            id: 0,
+            file_unique_id,
+            name,
+            file_uri,
+            mime_type,
+            expires_at,
+        }
+    }
+
+    pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO gemini_files (file_unique_id, name, file_uri, mime_type, expires_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&self.file_unique_id)
+        .bind(&self.name)
+        .bind(&self.file_uri)
+        .bind(&self.mime_type)
+        .bind(self.expires_at)
+        .execute(db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_file_unique_id(
+        file_unique_id: &str,
+        db: &Database,
+    ) -> Result<Option<GeminiFile>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT id, file_unique_id, name, file_uri, mime_type, expires_at FROM gemini_files WHERE file_unique_id = ?",
+        )
+        .bind(file_unique_id)
+        .fetch_optional(db.pool())
+        .await?;
+
+        match row {
+            Some(row) => {
+                let file = GeminiFile {
+                    id: row.try_get("id")?,
+                    file_unique_id: row.try_get("file_unique_id")?,
+                    name: row.try_get("name")?,
+                    file_uri: row.try_get("file_uri")?,
+                    mime_type: row.try_get("mime_type")?,
+                    expires_at: row.try_get("expires_at")?,
+                };
+                Ok(Some(file))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn delete_by_file_unique_id(
+        file_unique_id: &str,
+        db: &Database,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM gemini_files WHERE file_unique_id = ?")
+            .bind(file_unique_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+
+    /// Removes every file that expired before `timestamp`.
+    pub async fn delete_expired(timestamp: i64, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM gemini_files WHERE expires_at <= ?")
+            .bind(timestamp)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+}
@@ -12,6 +12,32 @@ pub struct Message {
     pub content: Option<String>,
     pub response: Option<String>,
     pub created_at: i64, // Unix timestamp
+    /// Media sent along with the content, replayed with the rest of the conversation.
+    pub attachments: Vec<Attachment>,
+}
+
+/// A telegram file attached to a message, the file itself is downloaded again when needed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Attachment {
+    pub file_id: String,
+    pub file_unique_id: String,
+    pub mime_type: String,
+}
+
+impl Attachment {
+    pub fn new(file_id: &str, file_unique_id: &str, mime_type: &str) -> Self {
+        Attachment {
+            file_id: file_id.to_string(),
+            file_unique_id: file_unique_id.to_string(),
+            mime_type: mime_type.to_string(),
+        }
+    }
+}
+
+fn parse_attachments(attachments: Option<String>) -> Vec<Attachment> {
+    attachments
+        .and_then(|x| serde_json::from_str(&x).ok())
+        .unwrap_or_default()
 }
 
 #[allow(dead_code)]
@@ -32,19 +58,26 @@ impl Message {
             content,
             response,
             created_at,
+            attachments: Vec::new(),
         }
     }
 
+    pub fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
     pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
         sqlx::query(
-            "INSERT OR REPLACE INTO messages (chat_id, sender_id, message_id, content, response, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO messages (chat_id, sender_id, message_id, content, response, created_at, attachments) VALUES (?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(self.chat_id)
         .bind(self.sender_id)
         .bind(self.message_id)
         .bind(&self.content)
         .bind(&self.response)
-        .bind(&self.created_at)
+        .bind(self.created_at)
+        .bind(serde_json::to_string(&self.attachments).unwrap())
         .execute(db.pool()) 
         .await?;
 
@@ -52,7 +85,7 @@ impl Message {
     }
 
     pub async fn find_by_id(id: i64, db: &Database) -> Result<Option<Message>, sqlx::Error> {
-        let row = sqlx::query("SELECT chat_id, sender_id, message_id, content, response, created_at FROM messages WHERE id = ?")
+        let row = sqlx::query("SELECT chat_id, sender_id, message_id, content, response, created_at, attachments FROM messages WHERE id = ?")
             .bind(id)
             .fetch_optional(db.pool()) 
             .await?;
@@ -67,6 +100,7 @@ impl Message {
                     content: row.try_get("content")?,
                     response: row.try_get("response")?,
                     created_at: row.try_get("created_at")?,
+                    attachments: parse_attachments(row.try_get("attachments")?),
                 };
                 Ok(Some(user))
             }
@@ -87,7 +121,7 @@ impl Message {
         db: &Database,
     ) -> Result<Option<Message>, sqlx::Error> {
         let row =
-            sqlx::query("SELECT id, chat_id, sender_id, message_id, content, response, created_at FROM messages WHERE sender_id = ?")
+            sqlx::query("SELECT id, chat_id, sender_id, message_id, content, response, created_at, attachments FROM messages WHERE sender_id = ?")
                 .bind(id)
                 .fetch_optional(db.pool())
                 .await?;
@@ -102,6 +136,7 @@ impl Message {
                     content: row.try_get("content")?,
                     response: row.try_get("response")?,
                     created_at: row.try_get("created_at")?,
+                    attachments: parse_attachments(row.try_get("attachments")?),
                 };
                 Ok(Some(user))
             }
@@ -115,7 +150,7 @@ impl Message {
         db: &Database,
     ) -> Result<Option<Message>, sqlx::Error> {
         let row = sqlx::query(
-            "SELECT id, chat_id, sender_id, message_id, content, response, created_at FROM messages WHERE message_id = ? AND chat_id = ?",
+            "SELECT id, chat_id, sender_id, message_id, content, response, created_at, attachments FROM messages WHERE message_id = ? AND chat_id = ?",
         )
         .bind(message_id)
         .bind(chat_id)
@@ -126,17 +161,56 @@ impl Message {
             Some(row) => {
                 let message = Message {
                     id: row.try_get("id")?,
-                    chat_id: chat_id,
+                    chat_id,
                     sender_id: row.try_get("sender_id")?,
                     message_id,
                     content: row.try_get("content")?,
                     response: row.try_get("response")?,
                     created_at: row.try_get("created_at")?,
+                    attachments: parse_attachments(row.try_get("attachments")?),
                 };
                 Ok(Some(message))
             }
             None => Ok(None),
         }
     }
-    
+
+    /// Most recent messages of the sender whose question or answer contains `term`, newest first.
+    pub async fn search_by_sender(
+        sender_id: i64,
+        term: &str,
+        limit: i64,
+        db: &Database,
+    ) -> Result<Vec<Message>, sqlx::Error> {
+        let pattern = format!(
+            "%{}%",
+            term.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let rows = sqlx::query(
+            "SELECT id, chat_id, sender_id, message_id, content, response, created_at, attachments FROM messages WHERE sender_id = ? AND (content LIKE ? ESCAPE '\\' OR response LIKE ? ESCAPE '\\') ORDER BY created_at DESC, id DESC LIMIT ?",
+        )
+        .bind(sender_id)
+        .bind(&pattern)
+        .bind(&pattern)
+        .bind(limit)
+        .fetch_all(db.pool())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Message {
+                    id: row.try_get("id")?,
+                    chat_id: row.try_get("chat_id")?,
+                    sender_id,
+                    message_id: row.try_get("message_id")?,
+                    content: row.try_get("content")?,
+                    response: row.try_get("response")?,
+                    created_at: row.try_get("created_at")?,
+                    attachments: parse_attachments(row.try_get("attachments")?),
+                })
+            })
+            .collect()
+    }
 }
@@ -8,6 +8,8 @@ pub struct MessageHistory {
     pub id: i64,
     pub user_id: i64,
     pub messages: Vec<i64>,
+    /// Summary of the turns dropped from `messages` to stay within the token budget.
+    pub summary: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -20,15 +22,19 @@ impl MessageHistory {
             id: 0,
             user_id,
             messages,
+            summary: None,
         }
     }
 
     pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT OR REPLACE INTO message_history (user_id, messages) VALUES (?, ?)")
-            .bind(self.user_id)
-            .bind(serde_json::to_string(&self.messages).unwrap())
-            .execute(db.pool())
-            .await?;
+        sqlx::query(
+            "INSERT OR REPLACE INTO message_history (user_id, messages, summary) VALUES (?, ?, ?)",
+        )
+        .bind(self.user_id)
+        .bind(serde_json::to_string(&self.messages).unwrap())
+        .bind(&self.summary)
+        .execute(db.pool())
+        .await?;
 
         Ok(())
     }
@@ -37,11 +43,12 @@ impl MessageHistory {
         user_id: i64,
         db: &Database,
     ) -> Result<Option<MessageHistory>, sqlx::Error> {
-        let row =
-            sqlx::query("SELECT id, user_id, messages FROM message_history WHERE user_id = ?")
-                .bind(user_id)
-                .fetch_optional(db.pool())
-                .await?;
+        let row = sqlx::query(
+            "SELECT id, user_id, messages, summary FROM message_history WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?;
 
         match row {
             Some(row) => {
@@ -49,6 +56,7 @@ impl MessageHistory {
                     id: row.try_get("id")?,
                     user_id: row.try_get("user_id")?,
                     messages: serde_json::from_str(row.try_get("messages").unwrap()).unwrap(),
+                    summary: row.try_get("summary")?,
                 };
                 Ok(Some(user))
             }
@@ -57,10 +65,11 @@ impl MessageHistory {
     }
 
     pub async fn find_by_id(id: i64, db: &Database) -> Result<Option<MessageHistory>, sqlx::Error> {
-        let row = sqlx::query("SELECT id, user_id, messages FROM message_history WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.pool())
-            .await?;
+        let row =
+            sqlx::query("SELECT id, user_id, messages, summary FROM message_history WHERE id = ?")
+                .bind(id)
+                .fetch_optional(db.pool())
+                .await?;
 
         match row {
             Some(row) => {
@@ -68,6 +77,7 @@ impl MessageHistory {
                     id: row.try_get("id")?,
                     user_id: row.try_get("user_id")?,
                     messages: serde_json::from_str(row.try_get("messages").unwrap()).unwrap(),
+                    summary: row.try_get("summary")?,
                 };
                 Ok(Some(user))
             }
@@ -92,8 +102,9 @@ impl MessageHistory {
     }
 
     pub async fn update(&self, db: &Database) -> Result<(), sqlx::Error> {
-        sqlx::query("UPDATE message_history SET messages = ? WHERE id = ?")
+        sqlx::query("UPDATE message_history SET messages = ?, summary = ? WHERE id = ?")
             .bind(serde_json::to_string(&self.messages).unwrap())
+            .bind(&self.summary)
             .bind(self.id)
             .execute(db.pool())
             .await?;
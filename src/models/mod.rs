@@ -2,3 +2,9 @@ pub mod user;
 pub mod message;
 pub mod message_history;
 pub mod gemini;
+pub mod user_settings;
+pub mod gemini_file;
+pub mod chat_settings;
+pub mod gemini_cache;
+pub mod user_api_key;
+pub mod openai;
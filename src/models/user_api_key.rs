@@ -0,0 +1,63 @@
+use crate::db::database::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::{self, Row};
+
+/// A Gemini API key registered by a user with /setkey, encrypted with the master key.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserApiKey {
+    pub id: i64,
+    pub user_id: i64,
+    pub encrypted_key: String,
+}
+
+#[allow(dead_code)]
+impl UserApiKey {
+    pub fn new(user_id: i64, encrypted_key: String) -> Self {
+        UserApiKey {
+            id: 0,
+            user_id,
+            encrypted_key,
+        }
+    }
+
+    pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT OR REPLACE INTO user_api_keys (user_id, encrypted_key) VALUES (?, ?)")
+            .bind(self.user_id)
+            .bind(&self.encrypted_key)
+            .execute(db.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_user_id(
+        user_id: i64,
+        db: &Database,
+    ) -> Result<Option<UserApiKey>, sqlx::Error> {
+        let row =
+            sqlx::query("SELECT id, user_id, encrypted_key FROM user_api_keys WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_optional(db.pool())
+                .await?;
+
+        match row {
+            Some(row) => {
+                let key = UserApiKey {
+                    id: row.try_get("id")?,
+                    user_id: row.try_get("user_id")?,
+                    encrypted_key: row.try_get("encrypted_key")?,
+                };
+                Ok(Some(key))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn delete_by_user_id(user_id: i64, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM user_api_keys WHERE user_id = ?")
+            .bind(user_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+}
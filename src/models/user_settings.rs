@@ -0,0 +1,169 @@
+use crate::db::database::Database;
+use crate::gemini::backend::BackendKind;
+use crate::models::gemini::{GenerationConfig, ThinkingConfig};
+use serde::{Deserialize, Serialize};
+use sqlx::{self, Row};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserSettings {
+    pub id: i64,
+    pub user_id: i64,
+    pub model: Option<String>,
+    /// IANA name such as `Europe/Berlin`, UTC is used when unset.
+    pub timezone: Option<String>,
+    /// Answers are grounded with Google Search.
+    pub grounding: bool,
+    /// Name of the /settings preset the sampling parameters come from.
+    pub preset: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<i64>,
+    pub max_output_tokens: Option<i64>,
+    pub stop_sequences: Vec<String>,
+    pub thinking_budget: Option<i64>,
+    /// `gemini` or `openai`, the deployment's default is used when unset.
+    pub backend: Option<String>,
+}
+
+#[allow(dead_code)]
+impl UserSettings {
+    pub fn new(user_id: i64) -> Self {
+        UserSettings {
+            id: 0,
+            user_id,
+            model: None,
+            timezone: None,
+            grounding: false,
+            preset: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            max_output_tokens: None,
+            stop_sequences: Vec::new(),
+            thinking_budget: None,
+            backend: None,
+        }
+    }
+
+    /// The `generationConfig` of the user's requests, None when everything is left to the API defaults.
+    pub fn generation_config(&self) -> Option<GenerationConfig> {
+        let config = GenerationConfig {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            top_k: self.top_k.map(|x| x as u32),
+            max_output_tokens: self.max_output_tokens.map(|x| x as u32),
+            stop_sequences: self.stop_sequences.clone(),
+            thinking_config: self.thinking_budget.map(|x| ThinkingConfig {
+                thinking_budget: Some(x as i32),
+            }),
+            ..Default::default()
+        };
+
+        if config == GenerationConfig::default() {
+            None
+        } else {
+            Some(config)
+        }
+    }
+
+    /// The backend the user picked, None when unset or no longer known.
+    pub fn backend(&self) -> Option<BackendKind> {
+        self.backend.as_deref().and_then(|x| x.parse().ok())
+    }
+
+    pub async fn insert(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO user_settings (user_id, model, timezone, grounding, preset, temperature, top_p, top_k, max_output_tokens, stop_sequences, thinking_budget, backend) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.user_id)
+        .bind(&self.model)
+        .bind(&self.timezone)
+        .bind(self.grounding)
+        .bind(&self.preset)
+        .bind(self.temperature)
+        .bind(self.top_p)
+        .bind(self.top_k)
+        .bind(self.max_output_tokens)
+        .bind(serde_json::to_string(&self.stop_sequences).unwrap())
+        .bind(self.thinking_budget)
+        .bind(&self.backend)
+        .execute(db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_user_id(
+        user_id: i64,
+        db: &Database,
+    ) -> Result<Option<UserSettings>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT id, user_id, model, timezone, grounding, preset, temperature, top_p, top_k, max_output_tokens, stop_sequences, thinking_budget, backend FROM user_settings WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?;
+
+        match row {
+            Some(row) => {
+                let stop_sequences: Option<String> = row.try_get("stop_sequences")?;
+                let settings = UserSettings {
+                    id: row.try_get("id")?,
+                    user_id: row.try_get("user_id")?,
+                    model: row.try_get("model")?,
+                    timezone: row.try_get("timezone")?,
+                    grounding: row.try_get("grounding")?,
+                    preset: row.try_get("preset")?,
+                    temperature: row.try_get("temperature")?,
+                    top_p: row.try_get("top_p")?,
+                    top_k: row.try_get("top_k")?,
+                    max_output_tokens: row.try_get("max_output_tokens")?,
+                    stop_sequences: stop_sequences
+                        .and_then(|x| serde_json::from_str(&x).ok())
+                        .unwrap_or_default(),
+                    thinking_budget: row.try_get("thinking_budget")?,
+                    backend: row.try_get("backend")?,
+                };
+                Ok(Some(settings))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Settings of the user, or the defaults if they never changed anything.
+    pub async fn find_or_default(user_id: i64, db: &Database) -> Result<UserSettings, sqlx::Error> {
+        Ok(Self::find_by_user_id(user_id, db)
+            .await?
+            .unwrap_or_else(|| Self::new(user_id)))
+    }
+
+    pub async fn update(&self, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE user_settings SET model = ?, timezone = ?, grounding = ?, preset = ?, temperature = ?, top_p = ?, top_k = ?, max_output_tokens = ?, stop_sequences = ?, thinking_budget = ?, backend = ? WHERE user_id = ?",
+        )
+        .bind(&self.model)
+        .bind(&self.timezone)
+        .bind(self.grounding)
+        .bind(&self.preset)
+        .bind(self.temperature)
+        .bind(self.top_p)
+        .bind(self.top_k)
+        .bind(self.max_output_tokens)
+        .bind(serde_json::to_string(&self.stop_sequences).unwrap())
+        .bind(self.thinking_budget)
+        .bind(&self.backend)
+        .bind(self.user_id)
+        .execute(db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_user_id(user_id: i64, db: &Database) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM user_settings WHERE user_id = ?")
+            .bind(user_id)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+// Self-hosted models behind an OpenAI-compatible API, e.g. llama.cpp server, vLLM or Ollama
+pub mod services;
+
+/// Where the OpenAI-compatible server is and which of its models answers.
+#[derive(Debug, Clone)]
+pub struct OpenAiSettings {
+    /// Up to and including the version, e.g. `http://localhost:8080/v1`.
+    pub base_url: String,
+    /// Sent as a bearer token, most self-hosted servers don't need one.
+    pub api_key: Option<String>,
+    pub model: String,
+}
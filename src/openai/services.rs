@@ -0,0 +1,289 @@
+// Chat completions of an OpenAI-compatible server, answering like the Gemini services
+use crate::app::config::AppConfig;
+use crate::gemini::backend::Prompt;
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{system_instruction, GeminiReply, GenerateOptions, ReplyPart};
+use crate::gemini::sse::SseParser;
+use crate::models::gemini::{Content, Part, PartData, UsageMetadata};
+use crate::models::openai::{
+    ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage, ContentPart, ImageUrl,
+};
+use crate::openai::OpenAiSettings;
+use crate::utils::redact;
+use base64::prelude::*;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Data of the event closing a streamed response.
+const DONE: &str = "[DONE]";
+
+/// Tokens assumed for an image, servers count them very differently.
+const IMAGE_TOKENS: i64 = 258;
+
+/// The prompt as a chat: the system instruction, then every earlier question and answer as
+/// user and assistant messages, then the question.
+pub fn chat_request(
+    settings: &OpenAiSettings,
+    prompt: Prompt<'_>,
+    options: &GenerateOptions,
+) -> ChatCompletionRequest {
+    let mut messages = vec![ChatMessage::text(
+        "system",
+        &system_instruction(prompt.instructions),
+    )];
+    for turn in prompt.history.as_deref().unwrap_or_default() {
+        messages.push(user_message(&turn.query, &turn.attachments));
+        messages.push(ChatMessage::text("assistant", &turn.response));
+    }
+    messages.push(user_message(prompt.query, prompt.attachments));
+
+    let generation_config = options.generation_config.clone().unwrap_or_default();
+    ChatCompletionRequest {
+        model: settings.model.clone(),
+        messages,
+        stream: false,
+        temperature: generation_config.temperature,
+        top_p: generation_config.top_p,
+        max_tokens: generation_config.max_output_tokens,
+        stop: generation_config.stop_sequences,
+    }
+}
+
+/// Text files are put before the question and images are sent as data urls.
+/// Other media, e.g. audio or PDFs, can't be sent and is left out.
+fn user_message(text: &str, attachments: &[Part]) -> ChatMessage {
+    let mut documents = Vec::new();
+    let mut images = Vec::new();
+    for part in attachments {
+        match &part.data {
+            PartData::Text(x) => documents.push(x.clone()),
+            PartData::InlineData(data) if data.mime_type.starts_with("image/") => {
+                images.push(ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: format!("data:{};base64,{}", data.mime_type, data.data),
+                    },
+                })
+            }
+            PartData::InlineData(data) if data.mime_type.starts_with("text/") => {
+                match BASE64_STANDARD.decode(&data.data) {
+                    Ok(bytes) => documents.push(String::from_utf8_lossy(&bytes).to_string()),
+                    Err(err) => log::warn!("Skipping a text attachment: {}", err),
+                }
+            }
+            PartData::InlineData(data) => log::warn!(
+                "Skipping a {} attachment, the OpenAI-compatible backend can't read it",
+                data.mime_type
+            ),
+            PartData::FileData(data) => log::warn!(
+                "Skipping an uploaded {} file, the OpenAI-compatible backend can't read it",
+                data.mime_type
+            ),
+            _ => {}
+        }
+    }
+
+    documents.push(text.to_string());
+    let text = documents.join("\n\n");
+    if images.is_empty() {
+        return ChatMessage::text("user", &text);
+    }
+    images.push(ContentPart::Text { text });
+    ChatMessage {
+        role: String::from("user"),
+        content: ChatContent::Parts(images),
+    }
+}
+
+pub async fn query_openai_api(
+    config: &Arc<AppConfig>,
+    settings: &OpenAiSettings,
+    prompt: Prompt<'_>,
+    options: &GenerateOptions,
+) -> Result<GeminiReply, GeminiError> {
+    let request = chat_request(settings, prompt, options);
+    let response = send_with_retry(config, settings, &request).await?;
+
+    let response_text = response.text().await?;
+    log::trace!(
+        "Response from {}: {}",
+        settings.model,
+        redact::content(&response_text, config.log_content)
+    );
+    let response = serde_json::from_str::<ChatCompletionResponse>(&response_text)
+        .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+
+    let mut reply = ChatReply::new(settings);
+    reply.push(response);
+    reply.finish()
+}
+
+/// Same as `query_openai_api` but streamed, sending the text generated so far through `updates`
+/// after every received chunk.
+pub async fn stream_openai_api(
+    config: &Arc<AppConfig>,
+    settings: &OpenAiSettings,
+    prompt: Prompt<'_>,
+    options: &GenerateOptions,
+    updates: mpsc::UnboundedSender<String>,
+) -> Result<GeminiReply, GeminiError> {
+    let mut request = chat_request(settings, prompt, options);
+    request.stream = true;
+    let mut response = send_with_retry(config, settings, &request).await?;
+
+    let mut reply = ChatReply::new(settings);
+    let mut parser = SseParser::new();
+    let push_event = |reply: &mut ChatReply, event: &str| -> Result<(), GeminiError> {
+        if event.trim() == DONE {
+            return Ok(());
+        }
+        let chunk = serde_json::from_str::<ChatCompletionResponse>(event)
+            .map_err(|err| GeminiError::MalformedBody(err.to_string()))?;
+        reply.push(chunk);
+        Ok(())
+    };
+
+    while let Some(chunk) = response.chunk().await? {
+        log::trace!(
+            "Chunk from {}: {}",
+            settings.model,
+            redact::content(&String::from_utf8_lossy(&chunk), config.log_content)
+        );
+        for event in parser.push(&chunk) {
+            push_event(&mut reply, &event)?;
+        }
+        _ = updates.send(reply.text.clone());
+    }
+    if let Some(event) = parser.finish() {
+        push_event(&mut reply, &event)?;
+    }
+
+    reply.finish()
+}
+
+/// Rough token count of the contents, these servers have no endpoint to count them.
+pub fn estimate_tokens(contents: &[Content]) -> i64 {
+    contents
+        .iter()
+        .flat_map(|x| &x.parts)
+        .map(|part| match &part.data {
+            PartData::Text(text) => (text.chars().count() as i64 + 3) / 4,
+            _ => IMAGE_TOKENS,
+        })
+        .sum()
+}
+
+/// Posts the request, retrying errors that may go away on their own according to the retry policy.
+async fn send_with_retry(
+    config: &Arc<AppConfig>,
+    settings: &OpenAiSettings,
+    request: &ChatCompletionRequest,
+) -> Result<reqwest::Response, GeminiError> {
+    let policy = &config.retry_policy;
+    let url = format!(
+        "{}/chat/completions",
+        settings.base_url.trim_end_matches('/')
+    );
+
+    let mut attempt = 0;
+    loop {
+        let mut builder = config
+            .http_client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(request).unwrap_or_default());
+        if let Some(api_key) = &settings.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+
+        let result = match builder.send().await {
+            Ok(response) => GeminiError::check_response(response).await,
+            Err(err) => Err(err.into()),
+        };
+        match result {
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_retryable() => {
+                log::warn!(
+                    "Request to {} failed (attempt {}): {}",
+                    settings.model,
+                    attempt + 1,
+                    err
+                );
+                match policy.delay(attempt, err.retry_after()) {
+                    Some(delay) if attempt < policy.max_retries => {
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    _ => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Collects a response, or the chunks of a streamed one, into a `GeminiReply`.
+struct ChatReply {
+    model: String,
+    text: String,
+    finish_reason: Option<String>,
+    usage: Option<UsageMetadata>,
+}
+
+impl ChatReply {
+    fn new(settings: &OpenAiSettings) -> Self {
+        Self {
+            model: settings.model.clone(),
+            text: String::new(),
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    fn push(&mut self, response: ChatCompletionResponse) {
+        if let Some(model) = response.model {
+            self.model = model;
+        }
+        if let Some(choice) = response.choices.into_iter().next() {
+            let content = choice
+                .message
+                .or(choice.delta)
+                .and_then(|x| x.content)
+                .unwrap_or_default();
+            self.text.push_str(&content);
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        if let Some(usage) = response.usage {
+            self.usage = Some(UsageMetadata {
+                prompt_token_count: usage.prompt_tokens,
+                candidates_token_count: usage.completion_tokens,
+                total_token_count: usage.total_tokens,
+                prompt_tokens_details: None,
+                candidates_tokens_details: None,
+            });
+        }
+    }
+
+    fn finish(self) -> Result<GeminiReply, GeminiError> {
+        if self.finish_reason.as_deref() == Some("content_filter") {
+            return Err(GeminiError::SafetyBlocked {
+                reason: String::from("content_filter"),
+                category: None,
+            });
+        }
+        if self.text.trim().is_empty() {
+            return Err(GeminiError::EmptyCandidates);
+        }
+
+        Ok(GeminiReply {
+            parts: vec![ReplyPart::Text(self.text.clone())],
+            text: self.text,
+            model: self.model.clone(),
+            usage_metadata: self.usage,
+            model_version: Some(self.model),
+            sources: Vec::new(),
+            search_queries: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bot::api_key;
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::models::gemini::Part;
+use crate::models::user_api_key::UserApiKey;
+use crate::tests::support::{test_config, MockResponse, MockServer, NO_SERVER, TEST_MASTER_KEY};
+use crate::utils::crypto::MasterKey;
+use crate::utils::redact;
+use std::sync::Arc;
+
+const PONG: &str = r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}],
+    "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 1, "totalTokenCount": 5}}"#;
+
+#[test]
+fn test_encryption_round_trip() {
+    let master_key = MasterKey::from_base64(TEST_MASTER_KEY).unwrap();
+
+    let sealed = master_key.encrypt("AIza-secret", b"user-1");
+    assert!(!sealed.contains("AIza-secret"));
+    assert_ne!(sealed, master_key.encrypt("AIza-secret", b"user-1"));
+    assert_eq!(
+        master_key.decrypt(&sealed, b"user-1").as_deref(),
+        Some("AIza-secret")
+    );
+
+    // Bound to the context it was encrypted for and to its exact bytes
+    assert_eq!(master_key.decrypt(&sealed, b"user-2"), None);
+    let mut tampered = sealed.into_bytes();
+    tampered[20] = if tampered[20] == b'A' { b'B' } else { b'A' };
+    assert_eq!(
+        master_key.decrypt(&String::from_utf8(tampered).unwrap(), b"user-1"),
+        None
+    );
+}
+
+#[test]
+fn test_master_key_must_have_32_bytes() {
+    assert!(MasterKey::from_base64("c2hvcnQ=").is_err());
+    assert!(MasterKey::from_base64("not base64!").is_err());
+}
+
+#[tokio::test]
+async fn test_set_key_validates_and_stores_encrypted() {
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"models": [{"name": "models/flash"}]}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let reply = api_key::set_key(&config, 7, "own-key-1234").await.unwrap();
+    assert!(reply.contains("...1234"));
+    assert!(!reply.contains("own-key"));
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].path, "/v1beta/models?pageSize=1");
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("own-key-1234"));
+
+    let stored = {
+        let db = config.database.lock().await;
+        UserApiKey::find_by_user_id(7, &db).await.unwrap().unwrap()
+    };
+    assert!(!stored.encrypted_key.contains("own-key-1234"));
+    assert_eq!(
+        api_key::user_key(&config, 7).await.as_deref(),
+        Some("own-key-1234")
+    );
+    assert_eq!(api_key::user_key(&config, 8).await, None);
+
+    api_key::remove_key(&config, 7).await;
+    assert_eq!(api_key::user_key(&config, 7).await, None);
+}
+
+#[tokio::test]
+async fn test_set_key_rejects_invalid_key() {
+    let server = MockServer::start(vec![MockResponse::json(
+        400,
+        r#"{"error": {"code": 400, "message": "API key not valid.", "status": "INVALID_ARGUMENT"}}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let err = api_key::set_key(&config, 7, "bad-key").await.unwrap_err();
+    assert!(err.contains("did not accept"));
+    assert_eq!(api_key::user_key(&config, 7).await, None);
+    // Only keys that are kept become secrets to redact
+    assert_eq!(redact::redact("sent bad-key"), "sent bad-key");
+}
+
+#[tokio::test]
+async fn test_set_key_without_master_key() {
+    let server = MockServer::start(Vec::new()).await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().master_key = None;
+
+    assert!(api_key::set_key(&config, 7, "own-key").await.is_err());
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_own_key_is_not_counted_against_the_pool() {
+    let server = MockServer::start(vec![
+        MockResponse::json(200, PONG),
+        MockResponse::json(200, PONG),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+    let options = GenerateOptions {
+        api_key: Some(String::from("own-key")),
+        ..Default::default()
+    };
+
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    // Not even requests referring to uploaded files fall back to the bot's keys
+    let file = Part::file_data("application/pdf", "https://example.com/files/abc");
+    query_gemini_api(
+        "ping",
+        &[file],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("own-key"));
+    assert_eq!(requests[1].header("x-goog-api-key"), Some("own-key"));
+
+    let usage = config.gemini_keys.usage();
+    assert_eq!(usage[0].requests, 0);
+    assert_eq!(usage[0].total_tokens, 0);
+}
+
+#[tokio::test]
+async fn test_own_key_sends_attachments_inline() {
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let mut options = GenerateOptions::default();
+    assert!(config.uses_files_api(&options));
+
+    options.api_key = Some(String::from("own-key"));
+    assert!(!config.uses_files_api(&options));
+}
@@ -0,0 +1,130 @@
+use crate::bot::bot_logic::generate_response;
+use crate::gemini::backend::Backend;
+use crate::gemini::history::{fit_to_budget, SUMMARY_QUERY};
+use crate::gemini::services::{GenerateOptions, HistoryTurn};
+use crate::models::message_history::MessageHistory;
+use crate::tests::fake_telegram::FakeTelegram;
+use crate::tests::support::{telegram_message, test_config, MockBackend, NO_SERVER};
+use std::sync::Arc;
+use teloxide::types::Message;
+
+#[tokio::test]
+async fn test_generate_response_offline() {
+    let telegram = FakeTelegram::start().await;
+    let bot = telegram.bot();
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let mock = Arc::new(MockBackend::new(&["Paris.", "About 2 million people."]));
+    let backend: Backend = mock.clone();
+
+    let question: Message =
+        serde_json::from_str(&telegram_message(1, "What is the capital of France?")).unwrap();
+    generate_response(
+        bot.clone(),
+        &question,
+        question.text().unwrap().to_string(),
+        Vec::new(),
+        config.clone(),
+        &backend,
+    )
+    .await
+    .unwrap();
+    let follow_up: Message =
+        serde_json::from_str(&telegram_message(2, "How many people live there?")).unwrap();
+    generate_response(
+        bot,
+        &follow_up,
+        follow_up.text().unwrap().to_string(),
+        Vec::new(),
+        config.clone(),
+        &backend,
+    )
+    .await
+    .unwrap();
+
+    // The follow-up is asked with the first exchange as history
+    let prompts = mock.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts.iter().all(|x| x.streamed));
+    assert!(prompts[0].history.is_empty());
+    assert_eq!(prompts[1].query, "How many people live there?");
+    assert_eq!(
+        prompts[1].history[0].query,
+        "What is the capital of France?"
+    );
+    assert_eq!(prompts[1].history[0].response, "Paris.");
+    assert_eq!(prompts[1].options.conversation, Some(7));
+
+    // A placeholder, then the answer edited into it, for both questions
+    let calls = telegram.calls();
+    assert_eq!(calls.len(), 4);
+    assert_eq!(calls[0].method, "SendMessage");
+    assert_eq!(calls[1].method, "EditMessageText");
+    assert!(calls[1].param("text").contains("Paris."));
+    assert!(calls[3].param("text").contains("About 2 million people."));
+
+    let db = config.database.lock().await;
+    let history = MessageHistory::find_by_user_id(7, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(history.messages.len(), 2);
+}
+
+#[tokio::test]
+async fn test_failed_answer_is_reported_and_not_stored() {
+    let telegram = FakeTelegram::start().await;
+    let bot = telegram.bot();
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let backend: Backend = Arc::new(MockBackend::default());
+
+    let question: Message = serde_json::from_str(&telegram_message(1, "Hello?")).unwrap();
+    generate_response(
+        bot,
+        &question,
+        String::from("Hello?"),
+        Vec::new(),
+        config.clone(),
+        &backend,
+    )
+    .await
+    .unwrap();
+
+    let calls = telegram.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[1].param("text").contains("did not return an answer"));
+    let db = config.database.lock().await;
+    assert!(MessageHistory::find_by_user_id(7, &db)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_history_is_summarized_offline() {
+    let mut config = test_config(NO_SERVER, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().history_token_budget = 100;
+    let backend = MockBackend::new(&["We talked about turns 0 to 3."]);
+    backend.push_token_count(150);
+    let turns: Vec<HistoryTurn> = (0..6)
+        .map(|i| HistoryTurn::new(format!("question {:031}", i), format!("answer {:033}", i)))
+        .collect();
+
+    let fitted = fit_to_budget(
+        &config,
+        &backend,
+        &GenerateOptions::default(),
+        None,
+        turns.clone(),
+    )
+    .await;
+
+    assert_eq!(fitted.summarized, 4);
+    assert_eq!(fitted.turns[0].query, SUMMARY_QUERY);
+    assert_eq!(fitted.turns[0].response, "We talked about turns 0 to 3.");
+    assert_eq!(fitted.turns[1].query, turns[4].query);
+
+    let prompts = backend.prompts();
+    assert_eq!(prompts.len(), 1);
+    assert!(prompts[0].query.starts_with("Summarize our conversation"));
+    assert_eq!(prompts[0].history.len(), 4);
+}
@@ -0,0 +1,287 @@
+use crate::gemini::backend::{Backend, BackendKind, GeminiBackend};
+use crate::models::chat_settings::ChatSettings;
+use crate::models::message_history::MessageHistory;
+use crate::models::user::User;
+use crate::openai::OpenAiSettings;
+use crate::tests::fake_gemini::{FakeGemini, GeminiMethod};
+use crate::tests::fake_telegram::{FakeTelegram, USER_ID};
+use crate::tests::support::{test_config, MockBackend, NO_SERVER};
+use serde_json::json;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_help_command() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let bot = telegram
+        .dispatch(config, Arc::new(MockBackend::default()))
+        .await;
+
+    telegram.send_text("/help");
+    let sent = telegram.wait_for("SendMessage", 1).await;
+    bot.stop().await;
+
+    assert_eq!(sent[0].params["chat_id"], USER_ID);
+    assert!(sent[0].param("text").contains("/newtopic"));
+    assert!(sent[0].param("text").contains("/backend"));
+    assert_eq!(telegram.calls_to("GetMe").len(), 1);
+}
+
+#[tokio::test]
+async fn test_question_answered_end_to_end() {
+    let telegram = FakeTelegram::start().await;
+    let gemini = FakeGemini::start().await;
+    gemini.stream(&["The capital of France ", "is Paris."]);
+    let config = test_config(&gemini.url, &["flash"]).await;
+    let backend: Backend = Arc::new(GeminiBackend);
+    let bot = telegram.dispatch(config.clone(), backend).await;
+
+    let question_id = telegram.send_text("What is the capital of France?");
+    telegram.wait_for("EditMessageText", 1).await;
+    // Stopping waits for the handler, so the final edit is in
+    bot.stop().await;
+    let edits = telegram.calls_to("EditMessageText");
+
+    // A placeholder answering the question, then the answer edited into it
+    let sent = telegram.calls_to("SendMessage");
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].params["reply_parameters"]["message_id"],
+        question_id
+    );
+    let placeholder_id = edits[0].params["message_id"].clone();
+    assert!(edits
+        .iter()
+        .all(|x| x.params["message_id"] == placeholder_id));
+    assert!(edits
+        .last()
+        .unwrap()
+        .param("text")
+        .starts_with("The capital of France is Paris."));
+
+    let requests = gemini.requests_to(GeminiMethod::StreamGenerateContent);
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].body.contains("What is the capital of France?"));
+
+    let db = config.database.lock().await;
+    assert!(User::find_by_id(USER_ID, &db).await.unwrap().is_some());
+    let history = MessageHistory::find_by_user_id(USER_ID, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(history.messages.len(), 1);
+}
+
+#[tokio::test]
+async fn test_long_answer_sent_as_document() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let answer = "All work and no play. ".repeat(200);
+    let backend: Backend = Arc::new(MockBackend::new(&[&answer]));
+    let bot = telegram.dispatch(config, backend).await;
+
+    telegram.send_text("Write a long story");
+    let documents = telegram.wait_for("SendDocument", 1).await;
+    bot.stop().await;
+
+    assert_eq!(documents[0].params["chat_id"], USER_ID);
+    assert_eq!(documents[0].param("document"), answer.trim_end());
+    assert!(documents[0]
+        .param("caption")
+        .contains("text file for your response"));
+}
+
+#[tokio::test]
+async fn test_new_topic_and_backend_commands() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let bot = telegram
+        .dispatch(config, Arc::new(MockBackend::default()))
+        .await;
+
+    telegram.send_text("/newtopic");
+    telegram.wait_for("SendMessage", 1).await;
+    telegram.send_text("/backend openai");
+    let sent = telegram.wait_for("SendMessage", 2).await;
+    bot.stop().await;
+
+    assert_eq!(
+        sent[0].param("text"),
+        "Your previous topic has been flushed."
+    );
+    assert_eq!(
+        sent[1].param("text"),
+        "Your requests are answered by gemini, no other backend is configured."
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_updates_are_ignored() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let bot = telegram
+        .dispatch(config, Arc::new(MockBackend::default()))
+        .await;
+
+    telegram.push_update(json!({
+        "my_chat_member": {
+            "chat": {"id": USER_ID, "type": "private", "first_name": "Ann"},
+            "from": {"id": USER_ID, "is_bot": false, "first_name": "Ann"},
+            "date": 1700000000,
+            "old_chat_member": {"status": "member", "user": {"id": 123, "is_bot": true, "first_name": "Zenith"}},
+            "new_chat_member": {"status": "kicked", "until_date": 0, "user": {"id": 123, "is_bot": true, "first_name": "Zenith"}}
+        }
+    }));
+    telegram.send_text("/help");
+    telegram.wait_for("SendMessage", 1).await;
+    bot.stop().await;
+
+    assert_eq!(telegram.calls_to("SendMessage").len(), 1);
+}
+
+#[tokio::test]
+async fn test_anonymous_admin_changes_safety() {
+    const GROUP_ID: i64 = -100;
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let bot = telegram
+        .dispatch(config.clone(), Arc::new(MockBackend::default()))
+        .await;
+
+    // Telegram sends messages of anonymous admins from GroupAnonymousBot, on behalf of the group
+    let group = json!({ "id": GROUP_ID, "type": "supergroup", "title": "Group" });
+    let text = "/safety harassment high";
+    telegram.push_update(json!({
+        "message": {
+            "message_id": 1,
+            "date": 1700000000,
+            "text": text,
+            "entities": [{ "type": "bot_command", "offset": 0, "length": 7 }],
+            "chat": group,
+            "sender_chat": group,
+            "from": { "id": 1087968824, "is_bot": true, "first_name": "Group", "username": "GroupAnonymousBot" },
+        }
+    }));
+    let sent = telegram.wait_for("SendMessage", 1).await;
+    bot.stop().await;
+
+    assert!(sent[0].param("text").contains("harassment: high"));
+    assert!(telegram.calls_to("GetChatMember").is_empty());
+    let db = config.database.lock().await;
+    let settings = ChatSettings::find_by_chat_id(GROUP_ID, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(settings.safety_settings.len(), 1);
+}
+
+#[tokio::test]
+async fn test_inline_query_is_answered() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let backend = Arc::new(MockBackend::new(&["Paris."]));
+    let bot = telegram.dispatch(config, backend.clone()).await;
+
+    // Only the finished query is asked, the one still being typed is dropped
+    let from = json!({ "id": USER_ID, "is_bot": false, "first_name": "Ann" });
+    for (id, query) in ["capital of France", "capital of France? !!"]
+        .into_iter()
+        .enumerate()
+    {
+        telegram.push_update(json!({
+            "inline_query": { "id": id.to_string(), "from": from, "query": query, "offset": "" }
+        }));
+    }
+    let answers = telegram.wait_for("AnswerInlineQuery", 1).await;
+    bot.stop().await;
+
+    assert_eq!(answers[0].param("inline_query_id"), "1");
+    let result = &answers[0].params["results"][0];
+    assert!(result["input_message_content"]["message_text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Paris."));
+    let prompts = backend.prompts();
+    assert_eq!(prompts.len(), 1);
+    assert_eq!(prompts[0].query, "capital of France? !!");
+}
+
+/// Queues `/transcribe` of `USER_ID` in their private chat, as a reply to their voice note.
+fn transcribe_voice_note(telegram: &FakeTelegram) {
+    telegram.add_file("voice-1", "OggS voice note");
+    let chat = json!({ "id": USER_ID, "type": "private", "first_name": "Ann" });
+    let from = json!({ "id": USER_ID, "is_bot": false, "first_name": "Ann" });
+    telegram.push_update(json!({
+        "message": {
+            "message_id": 2,
+            "date": 1700000000,
+            "text": "/transcribe",
+            "entities": [{ "type": "bot_command", "offset": 0, "length": 11 }],
+            "chat": chat,
+            "from": from,
+            "reply_to_message": {
+                "message_id": 1,
+                "date": 1700000000,
+                "chat": chat,
+                "from": from,
+                "voice": {
+                    "file_id": "voice-1",
+                    "file_unique_id": "voice-1",
+                    "duration": 3,
+                    "mime_type": "audio/ogg",
+                },
+            },
+        }
+    }));
+}
+
+#[tokio::test]
+async fn test_transcribe_applies_chat_safety() {
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    let backend = Arc::new(MockBackend::new(&["Hello there."]));
+    let bot = telegram.dispatch(config, backend.clone()).await;
+
+    transcribe_voice_note(&telegram);
+    telegram.wait_for("EditMessageText", 1).await;
+    bot.stop().await;
+
+    let edits = telegram.calls_to("EditMessageText");
+    assert!(edits[0].param("text").starts_with("Hello there."));
+    let prompts = backend.prompts();
+    assert_eq!(prompts.len(), 1);
+    // The private chat's limit, the user never used /safety
+    let safety_settings = &prompts[0].options.safety_settings;
+    assert_eq!(safety_settings.len(), 5);
+    assert!(safety_settings
+        .iter()
+        .all(|x| x.threshold == "BLOCK_ONLY_HIGH"));
+    assert_eq!(prompts[0].options.tool_user, None);
+    assert_eq!(prompts[0].options.conversation, None);
+}
+
+#[tokio::test]
+async fn test_transcribe_needs_gemini() {
+    let telegram = FakeTelegram::start().await;
+    let mut config = test_config(NO_SERVER, &["flash"]).await;
+    let settings = Arc::get_mut(&mut config).unwrap();
+    settings.openai = Some(OpenAiSettings {
+        base_url: String::from(NO_SERVER),
+        api_key: None,
+        model: String::from("llama-3"),
+    });
+    settings.default_backend = BackendKind::OpenAi;
+    let backend = Arc::new(MockBackend::new(&["A transcript made up."]));
+    let bot = telegram.dispatch(config, backend.clone()).await;
+
+    transcribe_voice_note(&telegram);
+    let sent = telegram.wait_for("SendMessage", 1).await;
+    bot.stop().await;
+
+    assert_eq!(
+        sent[0].param("text"),
+        "Transcription needs Gemini, switch to it with /backend gemini."
+    );
+    assert!(backend.prompts().is_empty());
+    assert!(telegram.calls_to("GetFile").is_empty());
+}
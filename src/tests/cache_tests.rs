@@ -0,0 +1,197 @@
+use crate::gemini::cache;
+use crate::gemini::services::{query_gemini_api, GenerateOptions, HistoryTurn};
+use crate::models::gemini::Part;
+use crate::models::gemini_cache::GeminiCache;
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use crate::utils::time::unix_timestamp;
+use serde_json::Value;
+use std::sync::Arc;
+
+const ANSWER: &str =
+    r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Chapter two."}]}}]}"#;
+
+/// A document followed by a question about something else.
+fn history() -> Arc<Option<Vec<HistoryTurn>>> {
+    let mut document = HistoryTurn::new(
+        "Read this book".to_string(),
+        "It is about caching.".to_string(),
+    );
+    document.attachments.push(Part::file_data(
+        "application/pdf",
+        "https://files.example/book",
+    ));
+    let followup = HistoryTurn::new("Thanks".to_string(), "You're welcome.".to_string());
+    Arc::from(Some(vec![document, followup]))
+}
+
+fn options() -> GenerateOptions {
+    GenerateOptions {
+        conversation: Some(1),
+        ..Default::default()
+    }
+}
+
+fn cache_response(expires_in: i64) -> MockResponse {
+    let expire_time = chrono::DateTime::from_timestamp(unix_timestamp() + expires_in, 0)
+        .unwrap()
+        .to_rfc3339();
+    MockResponse::json(
+        200,
+        &format!(
+            r#"{{"name": "cachedContents/book", "model": "models/flash", "expireTime": "{}"}}"#,
+            expire_time
+        ),
+    )
+}
+
+async fn ask(config: &Arc<crate::app::config::AppConfig>) {
+    query_gemini_api(
+        "Which chapter explains TTLs?",
+        &[],
+        &Arc::from(None),
+        config,
+        &history(),
+        &options(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_document_prefix_is_cached_and_reused() {
+    let server = MockServer::start(vec![
+        cache_response(3600),
+        MockResponse::json(200, ANSWER),
+        MockResponse::json(200, ANSWER),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    ask(&config).await;
+    ask(&config).await;
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+
+    assert_eq!(requests[0].path, "/v1beta/cachedContents");
+    let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert_eq!(body["model"], "models/flash");
+    assert_eq!(body["ttl"], "3600s");
+    assert!(body["systemInstruction"].is_object());
+    assert_eq!(body["contents"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        body["contents"][0]["parts"][0]["fileData"]["fileUri"],
+        "https://files.example/book"
+    );
+
+    // Both answers only send what comes after the document
+    for request in &requests[1..] {
+        assert!(request
+            .path
+            .starts_with("/v1beta/models/flash:generateContent"));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["cachedContent"], "cachedContents/book");
+        assert!(body.get("systemInstruction").is_none());
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[0]["parts"][0]["text"], "Thanks");
+    }
+
+    let db = config.database.lock().await;
+    let stored = GeminiCache::find_by_user_id(1, &db).await.unwrap().unwrap();
+    assert_eq!(stored.name.as_deref(), Some("cachedContents/book"));
+    assert_eq!(stored.prefix_len, 2);
+}
+
+#[tokio::test]
+async fn test_cache_ttl_is_refreshed() {
+    let server = MockServer::start(vec![
+        cache_response(1200),
+        MockResponse::json(200, ANSWER),
+        cache_response(3600),
+        MockResponse::json(200, ANSWER),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    ask(&config).await;
+    ask(&config).await;
+
+    // Less than half of the TTL was left, so the second message extends it
+    let requests = server.requests();
+    assert_eq!(requests[2].method, "PATCH");
+    assert!(requests[2]
+        .path
+        .starts_with("/v1beta/cachedContents/book?updateMask=ttl"));
+    assert_eq!(requests[2].body, r#"{"ttl":"3600s"}"#);
+
+    let db = config.database.lock().await;
+    let stored = GeminiCache::find_by_user_id(1, &db).await.unwrap().unwrap();
+    assert!(stored.expires_at > unix_timestamp() + 3000);
+}
+
+#[tokio::test]
+async fn test_failed_cache_is_not_retried() {
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            400,
+            r#"{"error": {"message": "Cached content is too small"}}"#,
+        ),
+        MockResponse::json(200, ANSWER),
+        MockResponse::json(200, ANSWER),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    ask(&config).await;
+    ask(&config).await;
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    for request in &requests[1..] {
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert!(body.get("cachedContent").is_none());
+        assert_eq!(body["contents"].as_array().unwrap().len(), 5);
+    }
+}
+
+#[tokio::test]
+async fn test_short_conversation_is_not_cached() {
+    let server = MockServer::start(vec![MockResponse::json(200, ANSWER)]).await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let history = Arc::from(Some(vec![HistoryTurn::new(
+        "Hi".to_string(),
+        "Hello!".to_string(),
+    )]));
+    query_gemini_api("Bye", &[], &Arc::from(None), &config, &history, &options())
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].body.contains("cachedContent"));
+}
+
+#[tokio::test]
+async fn test_delete_cache() {
+    let server = MockServer::start(vec![
+        cache_response(3600),
+        MockResponse::json(200, ANSWER),
+        MockResponse::json(200, "{}"),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    ask(&config).await;
+    cache::delete(&config, 1).await;
+
+    let requests = server.requests();
+    assert_eq!(requests[2].method, "DELETE");
+    assert_eq!(requests[2].path, "/v1beta/cachedContents/book");
+    let db = config.database.lock().await;
+    assert!(GeminiCache::find_by_user_id(1, &db)
+        .await
+        .unwrap()
+        .is_none());
+}
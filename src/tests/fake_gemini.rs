@@ -0,0 +1,156 @@
+// A Gemini API on localhost, so the whole request pipeline can be tested without network
+use crate::tests::support::{listen, MockResponse, RecordedRequest};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// The model methods the fake answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GeminiMethod {
+    GenerateContent,
+    StreamGenerateContent,
+    CountTokens,
+}
+
+impl GeminiMethod {
+    /// `/v1beta/models/flash:streamGenerateContent?alt=sse` -> `StreamGenerateContent`
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.split('?').next()?;
+        let (_, method) = path.strip_prefix("/v1beta/models/")?.rsplit_once(':')?;
+        match method {
+            "generateContent" => Some(GeminiMethod::GenerateContent),
+            "streamGenerateContent" => Some(GeminiMethod::StreamGenerateContent),
+            "countTokens" => Some(GeminiMethod::CountTokens),
+            _ => None,
+        }
+    }
+}
+
+/// In-process Gemini API answering `generateContent`, `streamGenerateContent` and `countTokens`
+/// with the next response scripted for the method. A method whose script ran out answers with
+/// a 500, other paths with a 404. Point `AppConfig::gemini_base_url` at it with `test_config`.
+pub struct FakeGemini {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    scripts: Arc<Mutex<HashMap<GeminiMethod, VecDeque<MockResponse>>>>,
+}
+
+impl FakeGemini {
+    pub async fn start() -> Self {
+        let scripts = Arc::new(Mutex::new(HashMap::<GeminiMethod, VecDeque<_>>::new()));
+        let pending = scripts.clone();
+        let (url, requests) =
+            listen(Arc::new(
+                move |request: &RecordedRequest| match GeminiMethod::from_path(&request.path) {
+                    Some(method) => pending
+                        .lock()
+                        .unwrap()
+                        .get_mut(&method)
+                        .and_then(|x| x.pop_front())
+                        .unwrap_or_else(|| {
+                            MockResponse::error(
+                                500,
+                                &format!("no response scripted for {:?}", method),
+                            )
+                        }),
+                    None => MockResponse::error(404, &format!("{} is not faked", request.path)),
+                },
+            ))
+            .await;
+
+        Self {
+            url,
+            requests,
+            scripts,
+        }
+    }
+
+    /// Adds a response to the end of the method's script.
+    pub fn push(&self, method: GeminiMethod, response: MockResponse) {
+        self.scripts
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .push_back(response);
+    }
+
+    /// `generateContent` answers with the text.
+    pub fn reply(&self, text: &str) {
+        self.push(
+            GeminiMethod::GenerateContent,
+            MockResponse::json(200, &response_body(text, true)),
+        );
+    }
+
+    /// `streamGenerateContent` sends the chunks of text one by one, the last one with the usage.
+    pub fn stream(&self, chunks: &[&str]) {
+        let events: Vec<String> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| response_body(chunk, i + 1 == chunks.len()))
+            .collect();
+        let events: Vec<&str> = events.iter().map(String::as_str).collect();
+        self.push(
+            GeminiMethod::StreamGenerateContent,
+            MockResponse::sse(&events),
+        );
+    }
+
+    /// `countTokens` reports the total.
+    pub fn token_count(&self, total: i64) {
+        self.push(
+            GeminiMethod::CountTokens,
+            MockResponse::json(200, &json!({ "totalTokens": total }).to_string()),
+        );
+    }
+
+    /// The method fails with the status, in the error format of the API.
+    pub fn error(&self, method: GeminiMethod, status: u16, message: &str) {
+        self.push(method, MockResponse::error(status, message));
+    }
+
+    /// The method answers with `src/tests/fixtures/gemini/<name>.json`.
+    pub fn fixture(&self, method: GeminiMethod, name: &str) {
+        self.push(method, MockResponse::json(200, &fixture(name)));
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Requests to the method, in the order they were received.
+    pub fn requests_to(&self, method: GeminiMethod) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|x| GeminiMethod::from_path(&x.path) == Some(method))
+            .collect()
+    }
+}
+
+/// A response with the text as the only part of its candidate, finished ones carry usage.
+fn response_body(text: &str, finished: bool) -> String {
+    let mut body = json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": text }] },
+        }],
+        "modelVersion": "gemini-2.0-flash",
+    });
+    if finished {
+        body["candidates"][0]["finishReason"] = json!("STOP");
+        body["usageMetadata"] = json!({
+            "promptTokenCount": 10,
+            "candidatesTokenCount": 5,
+            "totalTokenCount": 15,
+        });
+    }
+    body.to_string()
+}
+
+fn fixture(name: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tests/fixtures/gemini")
+        .join(format!("{}.json", name));
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("missing fixture {}: {}", path.display(), err))
+}
@@ -0,0 +1,224 @@
+use crate::bot::bot_logic::generate_response;
+use crate::gemini::backend::{Backend, GeminiBackend, GenerativeBackend, Prompt};
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{GenerateOptions, HistoryTurn};
+use crate::models::gemini::{Content, Part};
+use crate::models::message_history::MessageHistory;
+use crate::tests::fake_gemini::{FakeGemini, GeminiMethod};
+use crate::tests::fake_telegram::FakeTelegram;
+use crate::tests::support::{telegram_message, test_config};
+use serde_json::Value;
+use std::sync::Arc;
+use teloxide::types::Message;
+
+/// Questions without instructions or history.
+struct Questions {
+    instructions: Arc<Option<Vec<&'static str>>>,
+    history: Arc<Option<Vec<HistoryTurn>>>,
+}
+
+impl Questions {
+    fn new() -> Self {
+        Self {
+            instructions: Arc::from(None),
+            history: Arc::from(None),
+        }
+    }
+
+    fn prompt<'a>(&'a self, query: &'a str) -> Prompt<'a> {
+        Prompt {
+            query,
+            attachments: &[],
+            instructions: &self.instructions,
+            history: &self.history,
+        }
+    }
+}
+
+#[test]
+fn test_method_from_path() {
+    assert_eq!(
+        GeminiMethod::from_path("/v1beta/models/flash:streamGenerateContent?alt=sse"),
+        Some(GeminiMethod::StreamGenerateContent)
+    );
+    assert_eq!(
+        GeminiMethod::from_path("/v1beta/models/gemini-2.0-flash:countTokens"),
+        Some(GeminiMethod::CountTokens)
+    );
+    assert_eq!(GeminiMethod::from_path("/v1beta/files"), None);
+}
+
+#[tokio::test]
+async fn test_conversation_through_fake_gemini() {
+    let gemini = FakeGemini::start().await;
+    gemini.stream(&["Paris ", "is the capital."]);
+    gemini.token_count(18);
+    gemini.stream(&["About 2 million ", "people."]);
+    let telegram = FakeTelegram::start().await;
+    let bot = telegram.bot();
+    let mut config = test_config(&gemini.url, &["flash"]).await;
+    // Small enough that the follow-up's history is counted by the API
+    Arc::get_mut(&mut config).unwrap().history_token_budget = 20;
+    let backend: Backend = Arc::new(GeminiBackend);
+
+    for (id, text) in [
+        (1, "What is the capital of France?"),
+        (2, "How many people live there?"),
+    ] {
+        let question: Message = serde_json::from_str(&telegram_message(id, text)).unwrap();
+        generate_response(
+            bot.clone(),
+            &question,
+            text.to_string(),
+            Vec::new(),
+            config.clone(),
+            &backend,
+        )
+        .await
+        .unwrap();
+    }
+
+    let streamed = gemini.requests_to(GeminiMethod::StreamGenerateContent);
+    assert_eq!(streamed.len(), 2);
+    assert_eq!(
+        streamed[0].path,
+        "/v1beta/models/flash:streamGenerateContent?alt=sse"
+    );
+    assert_eq!(streamed[0].header("x-goog-api-key"), Some("test-key"));
+    let follow_up: Value = serde_json::from_str(&streamed[1].body).unwrap();
+    let contents = follow_up["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 3);
+    assert_eq!(contents[1]["parts"][0]["text"], "Paris is the capital.");
+    assert_eq!(gemini.requests_to(GeminiMethod::CountTokens).len(), 1);
+
+    let edits = telegram.calls_to("EditMessageText");
+    assert!(edits
+        .iter()
+        .any(|x| x.param("text").contains("Paris is the capital")));
+    assert!(edits
+        .last()
+        .unwrap()
+        .param("text")
+        .contains("About 2 million people"));
+
+    let db = config.database.lock().await;
+    let history = MessageHistory::find_by_user_id(7, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(history.messages.len(), 2);
+}
+
+#[tokio::test]
+async fn test_stream_updates_arrive_chunk_by_chunk() {
+    let gemini = FakeGemini::start().await;
+    gemini.stream(&["One, ", "two, ", "three."]);
+    let config = test_config(&gemini.url, &["flash"]).await;
+    let questions = Questions::new();
+
+    let (updates, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let reply = GeminiBackend
+        .stream(
+            &config,
+            questions.prompt("Count to three"),
+            &GenerateOptions::default(),
+            updates,
+        )
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "One, two, three.");
+    assert_eq!(reply.usage_metadata.unwrap().total_token_count, Some(15));
+
+    let mut received = Vec::new();
+    while let Ok(update) = receiver.try_recv() {
+        received.push(update);
+    }
+    assert_eq!(received, vec!["One, ", "One, two, ", "One, two, three."]);
+}
+
+#[tokio::test]
+async fn test_errors_of_fake_gemini() {
+    let gemini = FakeGemini::start().await;
+    gemini.error(
+        GeminiMethod::GenerateContent,
+        503,
+        "The model is overloaded.",
+    );
+    gemini.reply("Recovered.");
+    gemini.error(GeminiMethod::GenerateContent, 400, "API key not valid.");
+    gemini.fixture(GeminiMethod::GenerateContent, "prompt_blocked");
+    let config = test_config(&gemini.url, &["flash"]).await;
+    let questions = Questions::new();
+    let options = GenerateOptions::default();
+
+    let reply = GeminiBackend
+        .generate(&config, questions.prompt("Hello?"), &options)
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "Recovered.");
+    assert_eq!(gemini.requests().len(), 2);
+
+    match GeminiBackend
+        .generate(&config, questions.prompt("Hello?"), &options)
+        .await
+    {
+        Err(GeminiError::HttpStatus { status, message }) => {
+            assert_eq!(status, 400);
+            assert_eq!(message, "API key not valid.");
+        }
+        other => panic!("expected a 400, got {:?}", other.map(|x| x.text)),
+    }
+
+    match GeminiBackend
+        .generate(&config, questions.prompt("Hello?"), &options)
+        .await
+    {
+        Err(GeminiError::SafetyBlocked { reason, category }) => {
+            assert_eq!(reason, "SAFETY");
+            assert_eq!(category.as_deref(), Some("HARM_CATEGORY_HARASSMENT"));
+        }
+        other => panic!("expected a block, got {:?}", other.map(|x| x.text)),
+    }
+
+    // Nothing scripted anymore
+    let err = GeminiBackend
+        .count_tokens(
+            &config,
+            &options,
+            vec![Content::user(vec![Part::text("Hello?")])],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, GeminiError::HttpStatus { status: 500, .. }));
+}
+
+#[tokio::test]
+async fn test_fixture_and_token_count() {
+    let gemini = FakeGemini::start().await;
+    gemini.fixture(GeminiMethod::GenerateContent, "grounded");
+    gemini.token_count(7);
+    let config = test_config(&gemini.url, &["flash"]).await;
+    let questions = Questions::new();
+    let options = GenerateOptions::default();
+
+    let reply = GeminiBackend
+        .generate(&config, questions.prompt("Who won yesterday?"), &options)
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "The match ended 2-1.");
+    assert_eq!(reply.search_queries, vec!["match result yesterday"]);
+
+    let tokens = GeminiBackend
+        .count_tokens(
+            &config,
+            &options,
+            vec![Content::user(vec![Part::text("Who won?")])],
+        )
+        .await
+        .unwrap();
+    assert_eq!(tokens, 7);
+    assert_eq!(
+        gemini.requests_to(GeminiMethod::CountTokens)[0].path,
+        "/v1beta/models/flash:countTokens"
+    );
+}
@@ -0,0 +1,330 @@
+// A Telegram Bot API on localhost, so the dispatcher and handlers can be tested end to end
+use crate::app::config::AppConfig;
+use crate::bot::bot_logic::{setup_dispatcher, UserStates};
+use crate::gemini::backend::Backend;
+use crate::tests::support::{listen, MockResponse, RecordedRequest};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::dispatching::ShutdownToken;
+use teloxide::Bot;
+
+pub const BOT_TOKEN: &str = "123:TEST";
+/// The user and private chat synthetic messages come from.
+pub const USER_ID: i64 = 7;
+const BOT_USER_ID: i64 = 123;
+/// How long an empty `getUpdates` waits, a short long poll.
+const POLL_DELAY: Duration = Duration::from_millis(20);
+
+/// A method the bot called, with its parameters from the JSON or multipart body.
+/// Files sent with multipart are their content as text, under the name of the parameter.
+#[derive(Debug, Clone)]
+pub struct BotCall {
+    /// As teloxide names it in the path, e.g. `SendMessage`.
+    pub method: String,
+    pub params: Value,
+}
+
+impl BotCall {
+    /// A string parameter, e.g. the `text` of a message.
+    pub fn param(&self, name: &str) -> &str {
+        self.params[name].as_str().unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    updates: VecDeque<Value>,
+    next_update_id: i64,
+    next_message_id: i64,
+    /// Content of the files the bot can download, by file id.
+    files: HashMap<String, String>,
+}
+
+/// In-process Bot API for a bot created with `bot()`. Calls that send or edit messages are
+/// answered with a message of the chat, `getUpdates` delivers the synthetic updates queued with
+/// `send_text` or `push_update`, and methods without a result the bot looks at return `true`.
+/// Methods it doesn't know get a Bot API error.
+pub struct FakeTelegram {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeTelegram {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            next_update_id: 1,
+            next_message_id: 1000,
+            ..Default::default()
+        }));
+        let shared = state.clone();
+        let (url, requests) = listen(Arc::new(move |request: &RecordedRequest| {
+            let state = &mut shared.lock().unwrap();
+            // Downloads are `/file/bot<token>/<file_path>`, file paths are the file ids
+            if let Some(path) = request
+                .path
+                .strip_prefix(&format!("/file/bot{}/", BOT_TOKEN))
+            {
+                return match state.files.get(path) {
+                    Some(content) => MockResponse::json(200, content),
+                    None => MockResponse::error(404, "Not Found"),
+                };
+            }
+            answer(state, &parse_call(request))
+        }))
+        .await;
+
+        Self {
+            url,
+            requests,
+            state,
+        }
+    }
+
+    /// A bot whose requests go to this server instead of api.telegram.org.
+    pub fn bot(&self) -> Bot {
+        Bot::new(BOT_TOKEN).set_api_url(reqwest::Url::parse(&self.url).unwrap())
+    }
+
+    /// Queues a text message of `USER_ID` in their private chat, returns its message id.
+    pub fn send_text(&self, text: &str) -> i64 {
+        let message_id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_message_id += 1;
+            state.next_message_id
+        };
+        let mut message = message(message_id, USER_ID, text);
+        message["from"] = json!({ "id": USER_ID, "is_bot": false, "first_name": "Ann" });
+        if text.starts_with('/') {
+            let length = text.split_whitespace().next().unwrap_or_default().len();
+            message["entities"] = json!([{ "type": "bot_command", "offset": 0, "length": length }]);
+        }
+        self.push_update(json!({ "message": message }));
+        message_id
+    }
+
+    /// A file the bot can get with `getFile` and download, e.g. the voice note of a message.
+    pub fn add_file(&self, file_id: &str, content: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(file_id.to_string(), content.to_string());
+    }
+
+    /// Queues an update, the `update_id` is added.
+    pub fn push_update(&self, mut update: Value) {
+        let mut state = self.state.lock().unwrap();
+        update["update_id"] = json!(state.next_update_id);
+        state.next_update_id += 1;
+        state.updates.push_back(update);
+    }
+
+    /// Every call the bot made, in order.
+    pub fn calls(&self) -> Vec<BotCall> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(parse_call)
+            .collect()
+    }
+
+    /// The calls of one method, e.g. `SendMessage`.
+    pub fn calls_to(&self, method: &str) -> Vec<BotCall> {
+        self.calls()
+            .into_iter()
+            .filter(|x| x.method == method)
+            .collect()
+    }
+
+    /// Waits until the bot called the method `count` times, panics after a few seconds.
+    pub async fn wait_for(&self, method: &str, count: usize) -> Vec<BotCall> {
+        for _ in 0..500 {
+            let calls = self.calls_to(method);
+            if calls.len() >= count {
+                return calls;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!(
+            "expected {} calls to {}, got: {:#?}",
+            count,
+            method,
+            self.calls()
+        );
+    }
+
+    /// Runs the bot's dispatcher against this server until the returned bot is stopped.
+    pub async fn dispatch(&self, config: Arc<AppConfig>, backend: Backend) -> RunningBot {
+        let user_states: UserStates = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let mut dispatcher = setup_dispatcher(self.bot(), config, backend, user_states).await;
+        let shutdown = dispatcher.shutdown_token();
+        let task = tokio::spawn(async move { dispatcher.dispatch().await });
+        RunningBot { shutdown, task }
+    }
+}
+
+/// A dispatcher polling `FakeTelegram`.
+pub struct RunningBot {
+    shutdown: ShutdownToken,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl RunningBot {
+    /// Lets the dispatcher finish the updates it is handling and waits until it has stopped.
+    pub async fn stop(self) {
+        loop {
+            match self.shutdown.shutdown() {
+                Ok(stopped) => {
+                    stopped.await;
+                    break;
+                }
+                // Still starting up
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        self.task.await.unwrap();
+    }
+}
+
+fn answer(state: &mut State, call: &BotCall) -> MockResponse {
+    let result = match call.method.as_str() {
+        "GetMe" => json!({
+            "id": BOT_USER_ID,
+            "is_bot": true,
+            "first_name": "Zenith",
+            "username": "zenithgeminibot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": true,
+        }),
+        "GetWebhookInfo" => {
+            json!({ "url": "", "has_custom_certificate": false, "pending_update_count": 0 })
+        }
+        "GetUpdates" => {
+            let updates: Vec<Value> = state.updates.drain(..).collect();
+            if updates.is_empty() {
+                return ok(json!([])).with_delay(POLL_DELAY);
+            }
+            json!(updates)
+        }
+        "SendMessage" | "SendDocument" | "SendPhoto" => {
+            state.next_message_id += 1;
+            let text = match call.params.get("text") {
+                Some(text) => text.as_str(),
+                None => call.params["caption"].as_str(),
+            };
+            message(
+                state.next_message_id,
+                chat_id(call),
+                text.unwrap_or_default(),
+            )
+        }
+        "GetFile" => {
+            let file_id = call.param("file_id");
+            match state.files.get(file_id) {
+                Some(content) => json!({
+                    "file_id": file_id,
+                    "file_unique_id": file_id,
+                    "file_size": content.len(),
+                    "file_path": file_id,
+                }),
+                None => return error(&format!("Bad Request: invalid file_id {}", file_id)),
+            }
+        }
+        "EditMessageText" => message(
+            call.params["message_id"].as_i64().unwrap_or_default(),
+            chat_id(call),
+            call.param("text"),
+        ),
+        "DeleteMessage"
+        | "AnswerCallbackQuery"
+        | "AnswerInlineQuery"
+        | "SendChatAction"
+        | "DeleteWebhook" => json!(true),
+        method => return error(&format!("Bad Request: {} is not faked", method)),
+    };
+    ok(result)
+}
+
+/// A Bot API error, `ok` is false and the description says what was wrong.
+fn error(description: &str) -> MockResponse {
+    MockResponse::json(
+        200,
+        &json!({ "ok": false, "error_code": 400, "description": description }).to_string(),
+    )
+}
+
+fn ok(result: Value) -> MockResponse {
+    MockResponse::json(200, &json!({ "ok": true, "result": result }).to_string())
+}
+
+fn chat_id(call: &BotCall) -> i64 {
+    match &call.params["chat_id"] {
+        Value::String(x) => x.parse().unwrap_or_default(),
+        x => x.as_i64().unwrap_or_default(),
+    }
+}
+
+/// A text message in the private chat with `chat_id`.
+fn message(message_id: i64, chat_id: i64, text: &str) -> Value {
+    json!({
+        "message_id": message_id,
+        "date": 1700000000,
+        "text": text,
+        "chat": { "id": chat_id, "type": "private", "first_name": "Ann" },
+    })
+}
+
+/// `/bot123:TEST/SendMessage` with a JSON or multipart body.
+fn parse_call(request: &RecordedRequest) -> BotCall {
+    let method = request.path.rsplit('/').next().unwrap_or_default();
+    let boundary = request
+        .header("content-type")
+        .and_then(|x| x.split_once("boundary="))
+        .map(|(_, boundary)| boundary.trim_matches('"').to_string());
+    let params = match boundary {
+        Some(boundary) => multipart_params(&request.body, &boundary),
+        None => serde_json::from_str(&request.body).unwrap_or(Value::Null),
+    };
+
+    BotCall {
+        method: method.to_string(),
+        params,
+    }
+}
+
+fn multipart_params(body: &str, boundary: &str) -> Value {
+    let mut params = Map::new();
+    for part in body.split(&format!("--{}", boundary)) {
+        let Some((head, value)) = part.split_once("\r\n\r\n") else {
+            continue;
+        };
+        let name = head
+            .split("name=\"")
+            .nth(1)
+            .and_then(|x| x.split('"').next())
+            .unwrap_or_default();
+        let value = value.strip_suffix("\r\n").unwrap_or(value);
+        // Plain fields like `chat_id` are JSON values, files are kept as text
+        let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+        params.insert(name.to_string(), value);
+    }
+
+    // teloxide sends files as parts of their own, referred to as `attach://<part name>`
+    let attached: Vec<(String, String)> = params
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((
+                name.clone(),
+                value.as_str()?.strip_prefix("attach://")?.to_string(),
+            ))
+        })
+        .collect();
+    for (name, part) in attached {
+        if let Some(file) = params.remove(&part) {
+            params.insert(name, file);
+        }
+    }
+    Value::Object(params)
+}
@@ -0,0 +1,145 @@
+use crate::gemini::error::GeminiError;
+use crate::gemini::files::{find_active, upload_and_track, upload_file};
+use crate::models::gemini_file::GeminiFile;
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use crate::utils::time::unix_timestamp;
+
+fn file_body(state: &str) -> String {
+    format!(
+        r#"{{"name": "files/abc-123", "mimeType": "application/pdf", "sizeBytes": "11",
+            "expirationTime": "2025-03-17T12:00:00Z", "uri": "https://example.com/v1beta/files/abc-123",
+            "state": "{}"}}"#,
+        state
+    )
+}
+
+async fn start_upload_server() -> MockServer {
+    let server = MockServer::start(vec![]).await;
+    server.push(MockResponse::json(200, "{}").with_header(
+        "x-goog-upload-url",
+        &format!("{}/upload-session", server.url),
+    ));
+    server
+}
+
+#[tokio::test]
+async fn test_upload_file_waits_until_active() {
+    let server = start_upload_server().await;
+    server.push(MockResponse::json(
+        200,
+        &format!(r#"{{"file": {}}}"#, file_body("PROCESSING")),
+    ));
+    server.push(MockResponse::json(200, &file_body("ACTIVE")));
+    let config = test_config(&server.url, &["primary"]).await;
+
+    let file = upload_file(
+        &config,
+        b"%PDF-1.4 ...".to_vec(),
+        "application/pdf",
+        "report",
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(file.state.as_deref(), Some("ACTIVE"));
+    assert_eq!(
+        file.uri.as_deref(),
+        Some("https://example.com/v1beta/files/abc-123")
+    );
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+
+    assert_eq!(requests[0].path, "/upload/v1beta/files");
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+    assert!(requests[0].headers.contains(&(
+        String::from("x-goog-upload-protocol"),
+        String::from("resumable")
+    )));
+    assert!(requests[0].headers.contains(&(
+        String::from("x-goog-upload-header-content-length"),
+        String::from("12")
+    )));
+    assert!(requests[0].body.contains("report"));
+
+    assert_eq!(requests[1].path, "/upload-session");
+    assert!(requests[1].headers.contains(&(
+        String::from("x-goog-upload-command"),
+        String::from("upload, finalize")
+    )));
+    assert_eq!(requests[1].body, "%PDF-1.4 ...");
+
+    assert_eq!(requests[2].method, "GET");
+    assert_eq!(requests[2].path, "/v1beta/files/abc-123");
+    assert_eq!(requests[2].header("x-goog-api-key"), Some("test-key"));
+}
+
+#[tokio::test]
+async fn test_upload_file_processing_failed() {
+    let server = start_upload_server().await;
+    server.push(MockResponse::json(
+        200,
+        &format!(r#"{{"file": {}}}"#, file_body("FAILED")),
+    ));
+    let config = test_config(&server.url, &["primary"]).await;
+
+    let result = upload_file(&config, b"data".to_vec(), "application/pdf", "report").await;
+
+    assert!(matches!(result, Err(GeminiError::FileProcessing(_))));
+}
+
+#[tokio::test]
+async fn test_upload_is_tracked_and_reused() {
+    let server = start_upload_server().await;
+    let expires = chrono::Utc::now() + chrono::Duration::hours(48);
+    server.push(MockResponse::json(
+        200,
+        &format!(
+            r#"{{"file": {{"name": "files/abc-123", "uri": "https://example.com/v1beta/files/abc-123",
+                "expirationTime": "{}", "state": "ACTIVE"}}}}"#,
+            expires.to_rfc3339()
+        ),
+    ));
+    let config = test_config(&server.url, &["primary"]).await;
+
+    assert!(find_active(&config, "unique-id").await.is_none());
+
+    let file = upload_and_track(&config, "unique-id", b"data".to_vec(), "video/mp4")
+        .await
+        .unwrap();
+    assert_eq!(file.expires_at, expires.timestamp());
+
+    let found_file = find_active(&config, "unique-id").await.unwrap();
+    assert_eq!(
+        found_file.file_uri,
+        "https://example.com/v1beta/files/abc-123"
+    );
+    assert_eq!(found_file.mime_type, "video/mp4");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_expired_upload_is_forgotten() {
+    let config = test_config("http://127.0.0.1:1", &["primary"]).await;
+    {
+        let db = config.database.lock().await;
+        GeminiFile::new(
+            "unique-id".to_string(),
+            "files/abc-123".to_string(),
+            "https://example.com/v1beta/files/abc-123".to_string(),
+            "video/mp4".to_string(),
+            unix_timestamp() + 60,
+        )
+        .insert(&db)
+        .await
+        .unwrap();
+    }
+
+    assert!(find_active(&config, "unique-id").await.is_none());
+
+    let db = config.database.lock().await;
+    assert!(GeminiFile::find_by_file_unique_id("unique-id", &db)
+        .await
+        .unwrap()
+        .is_none());
+}
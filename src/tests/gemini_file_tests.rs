@@ -0,0 +1,57 @@
+use crate::models::gemini_file::GeminiFile;
+use crate::tests::support::setup_test_database;
+
+#[tokio::test]
+async fn test_gemini_file_insert_find_delete() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await;
+
+    let file = GeminiFile::new(
+        "unique-id".to_string(),
+        "files/abc-123".to_string(),
+        "https://example.com/v1beta/files/abc-123".to_string(),
+        "application/pdf".to_string(),
+        1000,
+    );
+    file.insert(&db).await?;
+
+    let found_file = GeminiFile::find_by_file_unique_id("unique-id", &db)
+        .await?
+        .unwrap();
+    assert_eq!(found_file.name, "files/abc-123");
+    assert_eq!(found_file.expires_at, 1000);
+
+    GeminiFile::delete_by_file_unique_id("unique-id", &db).await?;
+    assert!(GeminiFile::find_by_file_unique_id("unique-id", &db)
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gemini_file_delete_expired() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await;
+
+    for (unique_id, expires_at) in [("old", 1000), ("new", 3000)] {
+        GeminiFile::new(
+            unique_id.to_string(),
+            format!("files/{}", unique_id),
+            format!("https://example.com/v1beta/files/{}", unique_id),
+            "image/jpeg".to_string(),
+            expires_at,
+        )
+        .insert(&db)
+        .await?;
+    }
+
+    GeminiFile::delete_expired(2000, &db).await?;
+
+    assert!(GeminiFile::find_by_file_unique_id("old", &db)
+        .await?
+        .is_none());
+    assert!(GeminiFile::find_by_file_unique_id("new", &db)
+        .await?
+        .is_some());
+
+    Ok(())
+}
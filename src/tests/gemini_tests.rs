@@ -0,0 +1,179 @@
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{parse_reply, ReplyPart};
+use crate::models::gemini::{Part, PartData};
+use serde_json::json;
+
+#[test]
+fn test_parse_reply_text_and_citations() {
+    let body = r#"{
+        "candidates": [{
+            "content": {"parts": [{"text": "Paris"}], "role": "model"},
+            "finishReason": "STOP",
+            "citationMetadata": {"citationSources": [{"uri": "https://example.com"}]}
+        }],
+        "modelVersion": "gemini-2.0-flash"
+    }"#;
+
+    let reply = parse_reply(body, "gemini-2.0-flash").unwrap();
+    assert_eq!(reply.text, "Paris");
+    assert_eq!(reply.render(), "Paris\n\nSources:\n1. https://example.com");
+    assert_eq!(reply.model_version.as_deref(), Some("gemini-2.0-flash"));
+}
+
+#[test]
+fn test_parse_reply_grounding_sources() {
+    let body = r#"{
+        "candidates": [{
+            "content": {"parts": [{"text": "The match ended 2-1."}], "role": "model"},
+            "groundingMetadata": {
+                "webSearchQueries": ["match result yesterday"],
+                "searchEntryPoint": {"renderedContent": "<div>chip</div>"},
+                "groundingChunks": [
+                    {"web": {"uri": "https://news.example/a", "title": "news.example"}},
+                    {"web": {"uri": "https://unused.example", "title": "unused.example"}},
+                    {"web": {"uri": "https://sport.example/b", "title": "sport.example"}}
+                ],
+                "groundingSupports": [{
+                    "segment": {"startIndex": 0, "endIndex": 20, "text": "The match ended 2-1."},
+                    "groundingChunkIndices": [2, 0],
+                    "confidenceScores": [0.9, 0.8]
+                }]
+            },
+            "citationMetadata": {"citationSources": [{"uri": "https://news.example/a"}]}
+        }]
+    }"#;
+
+    let reply = parse_reply(body, "gemini-2.0-flash").unwrap();
+    assert_eq!(reply.text, "The match ended 2-1.");
+    assert_eq!(reply.search_queries, vec!["match result yesterday"]);
+    assert_eq!(
+        reply.render(),
+        "The match ended 2-1.\n\nSources:\n1. https://news.example/a\n2. sport.example: https://sport.example/b\n\nGoogle Search: match result yesterday"
+    );
+}
+
+#[test]
+fn test_parse_reply_prompt_blocked() {
+    let body = r#"{"promptFeedback": {"blockReason": "SAFETY"}}"#;
+
+    match parse_reply(body, "gemini-2.0-flash") {
+        Err(GeminiError::SafetyBlocked { reason, category }) => {
+            assert_eq!(reason, "SAFETY");
+            assert_eq!(category, None);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_reply_candidate_blocked() {
+    let body = r#"{"candidates": [{"finishReason": "SAFETY"}]}"#;
+
+    assert!(matches!(
+        parse_reply(body, "gemini-2.0-flash"),
+        Err(GeminiError::SafetyBlocked { .. })
+    ));
+}
+
+#[test]
+fn test_parse_reply_empty_and_malformed() {
+    assert!(matches!(
+        parse_reply(r#"{"candidates": []}"#, "gemini-2.0-flash"),
+        Err(GeminiError::EmptyCandidates)
+    ));
+    assert!(matches!(
+        parse_reply("Something went wrong", "gemini-2.0-flash"),
+        Err(GeminiError::MalformedBody(_))
+    ));
+}
+
+#[test]
+fn test_inline_data_part_serialization() {
+    let part = Part::inline_data("image/jpeg", b"jpeg bytes");
+
+    assert_eq!(
+        serde_json::to_value(&part).unwrap(),
+        json!({"inlineData": {"mimeType": "image/jpeg", "data": "anBlZyBieXRlcw=="}})
+    );
+    assert_eq!(
+        serde_json::to_value(Part::text("hi")).unwrap(),
+        json!({"text": "hi"})
+    );
+    assert!(!format!("{:?}", part).contains("anBlZyBieXRlcw=="));
+}
+
+#[test]
+fn test_parse_reply_keeps_every_part_kind_in_order() {
+    let body = r#"{
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"text": "Let me compute it.", "thought": true},
+                {"text": "Here is "},
+                {"text": "the plot."},
+                {"executableCode": {"language": "PYTHON", "code": "print(6 * 7)\n"}},
+                {"codeExecutionResult": {"outcome": "OUTCOME_OK", "output": "42\n"}},
+                {"inlineData": {"mimeType": "image/png", "data": "cG5n"}},
+                {"text": "Done."}
+            ]},
+            "finishReason": "STOP"
+        }]
+    }"#;
+
+    let reply = parse_reply(body, "gemini-2.0-flash").unwrap();
+    assert_eq!(
+        reply.parts,
+        vec![
+            ReplyPart::Thought(String::from("Let me compute it.")),
+            ReplyPart::Text(String::from("Here is the plot.")),
+            ReplyPart::Code {
+                language: String::from("PYTHON"),
+                code: String::from("print(6 * 7)\n")
+            },
+            ReplyPart::CodeResult {
+                outcome: String::from("OUTCOME_OK"),
+                output: String::from("42\n")
+            },
+            ReplyPart::Image {
+                mime_type: String::from("image/png"),
+                data: b"png".to_vec()
+            },
+            ReplyPart::Text(String::from("Done.")),
+        ]
+    );
+    assert_eq!(reply.text, "Here is the plot.Done.");
+    assert_eq!(
+        reply.render(),
+        "Here is the plot.\n\n```python\nprint(6 * 7)\n```\n\nOutput:\n42\n\nDone."
+    );
+    assert_eq!(reply.images().count(), 1);
+}
+
+#[test]
+fn test_parse_reply_image_only() {
+    let body = r#"{"candidates": [{"content": {"parts": [
+        {"inlineData": {"mimeType": "image/png", "data": "cG5n"}}
+    ]}}]}"#;
+
+    let reply = parse_reply(body, "gemini-2.0-flash").unwrap();
+    assert_eq!(reply.images().next(), Some(("image/png", &b"png"[..])));
+}
+
+#[test]
+fn test_part_round_trip() {
+    let json = json!({
+        "functionCall": {"name": "calculate", "args": {"expression": "1 + 1"}},
+        "thoughtSignature": "c2ln"
+    });
+
+    let part: Part = serde_json::from_value(json.clone()).unwrap();
+    assert!(matches!(&part.data, PartData::FunctionCall(call) if call.name == "calculate"));
+    assert!(!part.thought);
+    assert_eq!(serde_json::to_value(&part).unwrap(), json);
+
+    let thought: Part = serde_json::from_value(json!({"text": "hmm", "thought": true})).unwrap();
+    assert!(thought.thought);
+    assert_eq!(
+        serde_json::to_value(&thought).unwrap(),
+        json!({"text": "hmm", "thought": true})
+    );
+}
@@ -0,0 +1,163 @@
+use crate::gemini::backend::GeminiBackend;
+use crate::gemini::history::{estimate_tokens, fit_to_budget, SUMMARY_QUERY};
+use crate::gemini::services::{GenerateOptions, HistoryTurn};
+use crate::models::gemini::Part;
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Six turns of about 20 estimated tokens each.
+fn turns() -> Vec<HistoryTurn> {
+    (0..6)
+        .map(|i| HistoryTurn::new(format!("question {:031}", i), format!("answer {:033}", i)))
+        .collect()
+}
+
+#[test]
+fn test_estimate_tokens() {
+    assert_eq!(estimate_tokens(&turns()[0]), 20);
+
+    let mut turn = HistoryTurn::new("abc".to_string(), String::new());
+    assert_eq!(estimate_tokens(&turn), 1);
+    turn.attachments
+        .push(Part::inline_data("image/png", b"png"));
+    assert_eq!(estimate_tokens(&turn), 259);
+}
+
+#[tokio::test]
+async fn test_small_history_is_not_counted() {
+    let server = MockServer::start(Vec::new()).await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let fitted = fit_to_budget(
+        &config,
+        &GeminiBackend,
+        &GenerateOptions::default(),
+        None,
+        turns(),
+    )
+    .await;
+
+    assert_eq!(fitted.turns.len(), 6);
+    assert_eq!(fitted.summarized, 0);
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_oldest_turns_are_summarized() {
+    let server = MockServer::start(vec![
+        MockResponse::json(200, r#"{"totalTokens": 150}"#),
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "We compared questions 0 to 3."}]}}]}"#,
+        ),
+    ])
+    .await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().history_token_budget = 100;
+
+    let fitted = fit_to_budget(
+        &config,
+        &GeminiBackend,
+        &GenerateOptions::default(),
+        Some("Earlier summary".to_string()),
+        turns(),
+    )
+    .await;
+
+    // 150 counted tokens over an estimate of 132, the newest two turns fill half the budget
+    assert_eq!(fitted.summarized, 4);
+    assert_eq!(
+        fitted.summary.as_deref(),
+        Some("We compared questions 0 to 3.")
+    );
+    assert_eq!(fitted.turns.len(), 3);
+    assert_eq!(fitted.turns[0].query, SUMMARY_QUERY);
+    assert_eq!(fitted.turns[0].response, "We compared questions 0 to 3.");
+    assert_eq!(fitted.turns[1].query, turns()[4].query);
+
+    let requests = server.requests();
+    assert!(requests[0]
+        .path
+        .starts_with("/v1beta/models/flash:countTokens"));
+    let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 14);
+    assert!(body.get("systemInstruction").is_none());
+
+    // The previous summary and the four oldest turns, then the request for a summary
+    assert!(requests[1]
+        .path
+        .starts_with("/v1beta/models/flash:generateContent"));
+    let body: Value = serde_json::from_str(&requests[1].body).unwrap();
+    let contents = body["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 11);
+    assert_eq!(contents[1]["parts"][0]["text"], "Earlier summary");
+    assert!(contents[10]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Summarize our conversation"));
+}
+
+#[tokio::test]
+async fn test_failed_summary_keeps_stored_history() {
+    let server = MockServer::start(vec![
+        MockResponse::json(400, r#"{"error": {"message": "bad request"}}"#),
+        MockResponse::json(400, r#"{"error": {"message": "bad request"}}"#),
+    ])
+    .await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().history_token_budget = 100;
+
+    let fitted = fit_to_budget(
+        &config,
+        &GeminiBackend,
+        &GenerateOptions::default(),
+        Some("Earlier summary".to_string()),
+        turns(),
+    )
+    .await;
+
+    // The estimate is used when counting fails, nothing is stored when summarizing fails
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(fitted.summarized, 0);
+    assert!(fitted.summary.is_none());
+    assert_eq!(fitted.turns[0].response, "Earlier summary");
+    assert_eq!(fitted.turns.len(), 3);
+}
+
+#[tokio::test]
+async fn test_large_attachments_are_summarized() {
+    let server = MockServer::start(vec![
+        MockResponse::json(200, r#"{"totalTokens": 5000}"#),
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "You sent a long report."}]}}]}"#,
+        ),
+    ])
+    .await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().history_token_budget = 1000;
+    let mut history = turns();
+    history[0].attachments.push(Part::file_data(
+        "application/pdf",
+        "https://example.com/files/report",
+    ));
+
+    let fitted = fit_to_budget(
+        &config,
+        &GeminiBackend,
+        &GenerateOptions::default(),
+        None,
+        history,
+    )
+    .await;
+
+    // Estimated at 378 tokens, below half the budget, but the document makes up most of the count
+    assert!(server.requests()[0]
+        .path
+        .starts_with("/v1beta/models/flash:countTokens"));
+    assert_eq!(fitted.summarized, 1);
+    assert_eq!(fitted.summary.as_deref(), Some("You sent a long report."));
+    assert_eq!(fitted.turns.len(), 6);
+    assert_eq!(fitted.turns[1].query, turns()[1].query);
+}
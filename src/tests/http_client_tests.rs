@@ -0,0 +1,89 @@
+use crate::app::http::HttpSettings;
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const PONG: &str =
+    r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#;
+
+async fn ping(config: &Arc<crate::app::config::AppConfig>) -> Result<String, GeminiError> {
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await
+    .map(|reply| reply.text)
+}
+
+#[tokio::test]
+async fn test_stuck_request_times_out() {
+    let stuck = || MockResponse::json(200, PONG).with_delay(Duration::from_secs(30));
+    let server = MockServer::start(vec![stuck(), stuck(), stuck()]).await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().http_client = HttpSettings {
+        read_timeout: Duration::from_millis(100),
+        ..Default::default()
+    }
+    .build_client()
+    .unwrap();
+
+    let started = Instant::now();
+    match ping(&config).await {
+        Err(GeminiError::Transport(err)) => assert!(err.is_timeout()),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    // Every attempt gave up long before the server would have answered
+    assert_eq!(server.requests().len(), 3);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn test_user_agent_is_sent() {
+    let server = MockServer::start(vec![MockResponse::json(200, PONG)]).await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().http_client = HttpSettings {
+        user_agent: String::from("zenith-test/1.0"),
+        ..Default::default()
+    }
+    .build_client()
+    .unwrap();
+
+    assert_eq!(ping(&config).await.unwrap(), "pong");
+    assert_eq!(
+        server.requests()[0].header("user-agent"),
+        Some("zenith-test/1.0")
+    );
+}
+
+#[tokio::test]
+async fn test_requests_go_through_the_proxy() {
+    let proxy = MockServer::start(vec![MockResponse::json(200, PONG)]).await;
+    let mut config = test_config("http://gemini.invalid", &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().http_client = HttpSettings {
+        proxy: Some(proxy.url.clone()),
+        ..Default::default()
+    }
+    .build_client()
+    .unwrap();
+
+    assert_eq!(ping(&config).await.unwrap(), "pong");
+    assert_eq!(
+        proxy.requests()[0].path,
+        "http://gemini.invalid/v1beta/models/flash:generateContent"
+    );
+}
+
+#[test]
+fn test_invalid_proxy_is_rejected() {
+    let settings = HttpSettings {
+        proxy: Some(String::from("not a proxy url")),
+        ..Default::default()
+    };
+    assert!(settings.build_client().is_err());
+}
@@ -0,0 +1,177 @@
+use crate::gemini::error::GeminiError;
+use crate::gemini::keys::{self, KeyPool, KeySelection};
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::models::gemini::UsageMetadata;
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn pool(selection: KeySelection) -> KeyPool {
+    KeyPool::new(
+        vec![
+            "key-a".to_string(),
+            "key-b".to_string(),
+            "key-c".to_string(),
+        ],
+        selection,
+        Duration::from_secs(60),
+    )
+}
+
+fn usage(total: i64) -> UsageMetadata {
+    UsageMetadata {
+        prompt_token_count: Some(total - 1),
+        candidates_token_count: Some(1),
+        total_token_count: Some(total),
+        prompt_tokens_details: None,
+        candidates_tokens_details: None,
+    }
+}
+
+#[test]
+fn test_label() {
+    assert_eq!(keys::label("AIzaSyExample1234"), "...1234");
+    assert_eq!(keys::label("key-abcd1"), "...bcd1");
+    // Short keys would be mostly or entirely shown
+    assert_eq!(keys::label("key-abcd"), "...");
+    assert_eq!(keys::label("abc"), "...");
+    assert_eq!(keys::label(""), "...");
+}
+
+#[test]
+fn test_round_robin_skips_cooling_keys() {
+    let keys = pool(KeySelection::RoundRobin);
+
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-a");
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-b");
+    keys.cool_down("key-c", "flash", Some(Duration::from_secs(30)));
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-a");
+
+    // The cooldown only applies to the model that was rate limited
+    assert_eq!(keys.acquire("pro", false).unwrap(), "key-b");
+    assert_eq!(keys.acquire("pro", false).unwrap(), "key-c");
+
+    keys.cool_down("key-a", "flash", None);
+    keys.cool_down("key-b", "flash", Some(Duration::from_secs(10)));
+    assert!(!keys.available("flash", false));
+    let wait = keys
+        .acquire("flash", false)
+        .unwrap_err()
+        .retry_after()
+        .unwrap();
+    assert!(wait > Duration::from_secs(5) && wait <= Duration::from_secs(10));
+
+    let usage = keys.usage();
+    assert_eq!(usage[0].label, "...");
+    assert_eq!(usage[0].requests, 2);
+    assert_eq!(usage[0].rate_limited, 1);
+}
+
+#[test]
+fn test_least_used_selection() {
+    let keys = pool(KeySelection::LeastUsed);
+
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-a");
+    keys.record_usage("key-a", &usage(500));
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-b");
+    keys.record_usage("key-b", &usage(100));
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-c");
+    keys.record_usage("key-c", &usage(300));
+    assert_eq!(keys.acquire("flash", false).unwrap(), "key-b");
+
+    let usage = keys.usage();
+    assert_eq!(usage[0].total_tokens, 500);
+    assert_eq!(usage[0].prompt_tokens, 499);
+    assert_eq!(usage[0].output_tokens, 1);
+    assert_eq!(usage[1].requests, 2);
+}
+
+#[test]
+fn test_primary_only() {
+    let keys = pool(KeySelection::RoundRobin);
+
+    assert_eq!(keys.primary().as_deref(), Some("key-a"));
+    assert_eq!(keys.acquire("flash", true).unwrap(), "key-a");
+    assert_eq!(keys.acquire("flash", true).unwrap(), "key-a");
+    keys.cool_down("key-a", "flash", Some(Duration::from_secs(30)));
+    assert!(keys.acquire("flash", true).is_err());
+    assert!(keys.acquire("flash", false).is_ok());
+}
+
+#[test]
+fn test_empty_pool() {
+    // Deployments using only Vertex AI have no keys
+    let keys = KeyPool::new(
+        Vec::new(),
+        KeySelection::RoundRobin,
+        Duration::from_secs(60),
+    );
+
+    assert_eq!(keys.primary(), None);
+    for primary_only in [false, true] {
+        assert!(matches!(
+            keys.acquire("flash", primary_only),
+            Err(GeminiError::NoApiKey)
+        ));
+        assert!(!keys.available("flash", primary_only));
+    }
+}
+
+#[test]
+fn test_parse_key_selection() {
+    assert_eq!("least-used".parse(), Ok(KeySelection::LeastUsed));
+    assert_eq!("ROUND_ROBIN".parse(), Ok(KeySelection::RoundRobin));
+    assert!("random".parse::<KeySelection>().is_err());
+}
+
+#[tokio::test]
+async fn test_rate_limited_key_is_replaced_right_away() {
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            429,
+            r#"{"error": {"code": 429, "message": "Quota exceeded", "status": "RESOURCE_EXHAUSTED",
+                "details": [{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "3600s"}]}}"#,
+        ),
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}],
+                "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 1, "totalTokenCount": 5}}"#,
+        ),
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#,
+        ),
+    ])
+    .await;
+    let mut config = test_config(&server.url, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().gemini_keys = KeyPool::new(
+        vec!["key-a".to_string(), "key-b".to_string()],
+        KeySelection::RoundRobin,
+        Duration::from_secs(60),
+    );
+
+    for _ in 0..2 {
+        query_gemini_api(
+            "ping",
+            &[],
+            &Arc::from(None),
+            &config,
+            &Arc::from(None),
+            &GenerateOptions::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    // key-a stays out of rotation for the hour the response asked for
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("key-a"));
+    assert_eq!(requests[1].header("x-goog-api-key"), Some("key-b"));
+    assert_eq!(requests[2].header("x-goog-api-key"), Some("key-b"));
+
+    let usage = config.gemini_keys.usage();
+    assert_eq!(usage[0].rate_limited, 1);
+    assert_eq!(usage[1].requests, 2);
+    assert_eq!(usage[1].total_tokens, 5);
+}
@@ -0,0 +1,91 @@
+use crate::bot::media::{
+    audio_attachment, document_attachment, document_mime_type, photo_attachment, VOICE_MIME_TYPE,
+};
+use serde_json::{json, Value};
+use teloxide::types::Message;
+
+fn message(content: Value) -> Message {
+    let mut message = json!({
+        "message_id": 10,
+        "date": 1741000000,
+        "chat": {"id": 1, "type": "private", "first_name": "Test"},
+        "from": {"id": 1, "is_bot": false, "first_name": "Test"}
+    });
+    message
+        .as_object_mut()
+        .unwrap()
+        .extend(content.as_object().unwrap().clone());
+    serde_json::from_value(message).unwrap()
+}
+
+#[test]
+fn test_photo_attachment_uses_largest_size() {
+    let msg = message(json!({
+        "caption": "what is this?",
+        "photo": [
+            {"file_id": "small", "file_unique_id": "s", "file_size": 100, "width": 90, "height": 60},
+            {"file_id": "large", "file_unique_id": "l", "file_size": 9000, "width": 1280, "height": 853},
+            {"file_id": "medium", "file_unique_id": "m", "file_size": 900, "width": 320, "height": 213}
+        ]
+    }));
+
+    let attachment = photo_attachment(&msg).unwrap();
+    assert_eq!(attachment.file_id, "large");
+    assert_eq!(attachment.mime_type, "image/jpeg");
+    assert!(audio_attachment(&msg).is_none());
+}
+
+#[test]
+fn test_voice_and_audio_attachments() {
+    let voice = message(json!({
+        "voice": {"file_id": "voice", "file_unique_id": "v", "file_size": 1000, "duration": 3, "mime_type": "audio/ogg"}
+    }));
+    let attachment = audio_attachment(&voice).unwrap();
+    assert_eq!(attachment.file_id, "voice");
+    assert_eq!(attachment.mime_type, VOICE_MIME_TYPE);
+
+    let video_note = message(json!({
+        "video_note": {"file_id": "note", "file_unique_id": "n", "file_size": 1000, "duration": 3, "length": 240}
+    }));
+    assert!(audio_attachment(&video_note).is_none());
+
+    let audio = message(json!({
+        "audio": {"file_id": "audio", "file_unique_id": "a", "file_size": 1000, "duration": 120, "mime_type": "audio/x-wav"}
+    }));
+    let attachment = audio_attachment(&audio).unwrap();
+    assert_eq!(attachment.file_id, "audio");
+    assert_eq!(attachment.mime_type, "audio/x-wav");
+
+    let text = message(json!({"text": "hello"}));
+    assert!(audio_attachment(&text).is_none());
+    assert!(photo_attachment(&text).is_none());
+}
+
+#[test]
+fn test_document_mime_type() {
+    assert_eq!(
+        document_mime_type(Some("application/pdf"), Some("report.pdf")).as_deref(),
+        Some("application/pdf")
+    );
+    assert_eq!(
+        document_mime_type(Some("text/markdown"), Some("README.md")).as_deref(),
+        Some("text/plain")
+    );
+    assert_eq!(
+        document_mime_type(Some("application/octet-stream"), Some("main.RS")).as_deref(),
+        Some("text/plain")
+    );
+    assert_eq!(document_mime_type(None, Some("archive.zip")), None);
+    assert_eq!(document_mime_type(Some("application/zip"), None), None);
+}
+
+#[test]
+fn test_document_attachment() {
+    let msg = message(json!({
+        "document": {"file_id": "doc", "file_unique_id": "d", "file_size": 1000, "file_name": "notes.txt", "mime_type": "text/plain"}
+    }));
+
+    let attachment = document_attachment(&msg).unwrap();
+    assert_eq!(attachment.file_id, "doc");
+    assert_eq!(attachment.mime_type, "text/plain");
+}
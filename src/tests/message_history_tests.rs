@@ -29,6 +29,7 @@ async fn test_migration_and_history_insert() -> Result<(), sqlx::Error> {
         id: 0,
         user_id: 1,
         messages: vec![9, 8, 7, 1, 2],
+        summary: None,
     };
 
     history.insert(&db).await.unwrap();
@@ -44,6 +45,7 @@ async fn test_history_insert_find_delete_update() -> Result<(), sqlx::Error> {
         id: 0,
         user_id: 2,
         messages: vec![9, 8, 7, 1, 2, 3, 4, 5],
+        summary: None,
     };
 
     history.insert(&db).await.unwrap();
@@ -53,6 +55,7 @@ async fn test_history_insert_find_delete_update() -> Result<(), sqlx::Error> {
 
     let mut history = found_history.clone();
     history.messages = vec![9, 8, 7, 1, 2, 3, 4, 5, 10, 12, 16];
+    history.summary = Some("We talked about rust.".to_string());
 
     history.update(&db).await?;
     let found_updated_history = MessageHistory::find_by_user_id(found_history.user_id, &db)
@@ -62,6 +65,10 @@ async fn test_history_insert_find_delete_update() -> Result<(), sqlx::Error> {
         found_updated_history.messages,
         vec![9, 8, 7, 1, 2, 3, 4, 5, 10, 12, 16]
     );
+    assert_eq!(
+        found_updated_history.summary.as_deref(),
+        Some("We talked about rust.")
+    );
 
     MessageHistory::delete_by_id(history.id, &db).await?;
 
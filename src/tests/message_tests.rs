@@ -1,5 +1,5 @@
 use crate::db::database::Database;
-use crate::models::message::Message;
+use crate::models::message::{Attachment, Message};
 use crate::utils::time::unix_timestamp;
 use sqlx::migrate::Migrator;
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
@@ -82,3 +82,22 @@ async fn test_message_insert_delete_id() -> Result<(), sqlx::Error> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_message_attachments_round_trip() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await?;
+
+    let attachment = Attachment::new("file-id", "unique-id", "image/jpeg");
+    let message = Message::new(1, 2, 4, Some("content".to_string()), Some("response".to_string()), unix_timestamp())
+        .with_attachments(vec![attachment.clone()]);
+
+    message.insert(&db).await.unwrap();
+
+    let found_message = Message::find_by_message_and_chat_id(4, 1, &db).await?.unwrap();
+    assert_eq!(found_message.attachments, vec![attachment]);
+
+    let found_message = Message::find_by_id(found_message.id, &db).await?.unwrap();
+    assert_eq!(found_message.attachments.len(), 1);
+
+    Ok(())
+}
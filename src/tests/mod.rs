@@ -8,4 +8,79 @@ mod user_tests;
 mod message_history_tests;
 
 #[cfg(test)]
-mod message_tests;
\ No newline at end of file
+mod message_tests;
+
+#[cfg(test)]
+mod sse_tests;
+
+#[cfg(test)]
+mod gemini_tests;
+
+#[cfg(test)]
+mod support;
+
+#[cfg(test)]
+mod fake_gemini;
+
+#[cfg(test)]
+mod fake_telegram;
+
+#[cfg(test)]
+mod retry_tests;
+
+#[cfg(test)]
+mod user_settings_tests;
+
+#[cfg(test)]
+mod media_tests;
+
+#[cfg(test)]
+mod files_tests;
+
+#[cfg(test)]
+mod gemini_file_tests;
+
+#[cfg(test)]
+mod tools_tests;
+
+#[cfg(test)]
+mod request_tests;
+
+#[cfg(test)]
+mod settings_tests;
+
+#[cfg(test)]
+mod safety_tests;
+
+#[cfg(test)]
+mod history_tests;
+
+#[cfg(test)]
+mod cache_tests;
+
+#[cfg(test)]
+mod keys_tests;
+
+#[cfg(test)]
+mod api_key_tests;
+
+#[cfg(test)]
+mod logging_tests;
+
+#[cfg(test)]
+mod http_client_tests;
+
+#[cfg(test)]
+mod backend_tests;
+
+#[cfg(test)]
+mod openai_tests;
+
+#[cfg(test)]
+mod vertex_tests;
+
+#[cfg(test)]
+mod fake_gemini_tests;
+
+#[cfg(test)]
+mod bot_tests;
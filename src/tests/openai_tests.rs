@@ -0,0 +1,256 @@
+use crate::gemini::backend::{
+    Backend, BackendKind, ConfiguredBackend, GenerativeBackend, OpenAiBackend, Prompt,
+};
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{GenerateOptions, HistoryTurn};
+use crate::models::gemini::{GenerationConfig, Part};
+use crate::models::user_settings::UserSettings;
+use crate::openai::services::chat_request;
+use crate::openai::OpenAiSettings;
+use crate::tests::fake_telegram::{FakeTelegram, USER_ID};
+use crate::tests::support::{test_config, MockResponse, MockServer, NO_SERVER};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const COMPLETION: &str = r#"{
+    "id": "chatcmpl-1", "object": "chat.completion", "model": "llama-3",
+    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Paris."}, "finish_reason": "stop"}],
+    "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+}"#;
+
+fn settings(server: &MockServer, api_key: Option<&str>) -> OpenAiSettings {
+    OpenAiSettings {
+        base_url: format!("{}/v1/", server.url),
+        api_key: api_key.map(String::from),
+        model: String::from("llama-3"),
+    }
+}
+
+fn sent_body(server: &MockServer, index: usize) -> Value {
+    serde_json::from_str(&server.requests()[index].body).unwrap()
+}
+
+#[test]
+fn test_chat_request_maps_history_and_instructions() {
+    let settings = OpenAiSettings {
+        base_url: String::from("http://localhost"),
+        api_key: None,
+        model: String::from("llama-3"),
+    };
+    let mut turn = HistoryTurn::new(
+        String::from("What is on this picture?"),
+        String::from("A cat."),
+    );
+    turn.attachments
+        .push(Part::inline_data("image/jpeg", &[1, 2, 3]));
+    turn.attachments
+        .push(Part::inline_data("text/plain", b"Cats sleep a lot."));
+    let history = Arc::from(Some(vec![turn]));
+    let instructions = Arc::from(Some(vec!["Answer in one sentence."]));
+    let prompt = Prompt {
+        query: "How long does it sleep?",
+        attachments: &[],
+        instructions: &instructions,
+        history: &history,
+    };
+    let options = GenerateOptions {
+        generation_config: Some(GenerationConfig {
+            temperature: Some(0.5),
+            max_output_tokens: Some(100),
+            stop_sequences: vec![String::from("END")],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let request = serde_json::to_value(chat_request(&settings, prompt, &options)).unwrap();
+    assert_eq!(request["model"], "llama-3");
+    assert_eq!(request["temperature"], 0.5);
+    assert_eq!(request["max_tokens"], 100);
+    assert_eq!(request["stop"], json!(["END"]));
+    assert!(request.get("stream").is_none());
+
+    let messages = request["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["role"], "system");
+    assert!(messages[0]["content"]
+        .as_str()
+        .unwrap()
+        .ends_with("Answer in one sentence."));
+    assert_eq!(messages[1]["role"], "user");
+    assert_eq!(messages[1]["content"][0]["type"], "image_url");
+    assert_eq!(
+        messages[1]["content"][0]["image_url"]["url"],
+        "data:image/jpeg;base64,AQID"
+    );
+    assert_eq!(
+        messages[1]["content"][1]["text"],
+        "Cats sleep a lot.\n\nWhat is on this picture?"
+    );
+    assert_eq!(
+        messages[2],
+        json!({"role": "assistant", "content": "A cat."})
+    );
+    assert_eq!(
+        messages[3],
+        json!({"role": "user", "content": "How long does it sleep?"})
+    );
+}
+
+#[tokio::test]
+async fn test_generate_with_openai_backend() {
+    let server = MockServer::start(vec![MockResponse::json(200, COMPLETION)]).await;
+    let config = test_config(&server.url, &["flash"]).await;
+    let backend = OpenAiBackend {
+        settings: settings(&server, Some("sk-local")),
+    };
+
+    let prompt = Prompt {
+        query: "What is the capital of France?",
+        attachments: &[],
+        instructions: &Arc::from(None),
+        history: &Arc::from(None),
+    };
+    let reply = backend
+        .generate(&config, prompt, &GenerateOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "Paris.");
+    assert_eq!(reply.model, "llama-3");
+    let usage = reply.usage_metadata.unwrap();
+    assert_eq!(usage.prompt_token_count, Some(12));
+    assert_eq!(usage.total_token_count, Some(15));
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v1/chat/completions");
+    assert_eq!(requests[0].header("authorization"), Some("Bearer sk-local"));
+    assert_eq!(
+        sent_body(&server, 0)["messages"][1]["content"],
+        "What is the capital of France?"
+    );
+}
+
+#[tokio::test]
+async fn test_stream_with_openai_backend() {
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"model": "llama-3", "choices": [{"index": 0, "delta": {"role": "assistant", "content": ""}}]}"#,
+        r#"{"model": "llama-3", "choices": [{"index": 0, "delta": {"content": "Par"}}]}"#,
+        r#"{"model": "llama-3", "choices": [{"index": 0, "delta": {"content": "is."}, "finish_reason": "stop"}]}"#,
+        "[DONE]",
+    ])])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+    let backend = OpenAiBackend {
+        settings: settings(&server, None),
+    };
+
+    let (updates, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let prompt = Prompt {
+        query: "What is the capital of France?",
+        attachments: &[],
+        instructions: &Arc::from(None),
+        history: &Arc::from(None),
+    };
+    let reply = backend
+        .stream(&config, prompt, &GenerateOptions::default(), updates)
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "Paris.");
+
+    let mut last_update = String::new();
+    while let Ok(update) = receiver.try_recv() {
+        last_update = update;
+    }
+    assert_eq!(last_update, "Paris.");
+
+    assert_eq!(sent_body(&server, 0)["stream"], true);
+    assert_eq!(server.requests()[0].header("authorization"), None);
+}
+
+#[tokio::test]
+async fn test_openai_errors_are_retried_and_reported() {
+    let server = MockServer::start(vec![
+        MockResponse::json(503, r#"{"error": {"message": "model is loading"}}"#),
+        MockResponse::json(200, COMPLETION),
+        MockResponse::json(
+            200,
+            r#"{"choices": [{"index": 0, "message": {"content": ""}, "finish_reason": "content_filter"}]}"#,
+        ),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+    let backend = OpenAiBackend {
+        settings: settings(&server, None),
+    };
+    let prompt = Prompt {
+        query: "Hello?",
+        attachments: &[],
+        instructions: &Arc::from(None),
+        history: &Arc::from(None),
+    };
+
+    let reply = backend
+        .generate(&config, prompt, &GenerateOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(reply.text, "Paris.");
+    assert_eq!(server.requests().len(), 2);
+
+    let err = backend
+        .generate(&config, prompt, &GenerateOptions::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, GeminiError::SafetyBlocked { .. }));
+}
+
+#[tokio::test]
+async fn test_backend_is_picked_per_user() {
+    let openai = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"model": "llama-3", "choices": [{"index": 0, "delta": {"content": "Paris."}}]}"#,
+        "[DONE]",
+    ])])
+    .await;
+    let telegram = FakeTelegram::start().await;
+
+    // Gemini stays the default, requests to it would fail as nothing listens there
+    let mut config = test_config(NO_SERVER, &["flash"]).await;
+    Arc::get_mut(&mut config).unwrap().openai = Some(settings(&openai, None));
+    assert_eq!(config.backend(None), BackendKind::Gemini);
+    assert_eq!(
+        config.backend(Some(BackendKind::OpenAi)),
+        BackendKind::OpenAi
+    );
+    let backend: Backend = Arc::new(ConfiguredBackend::new(&config));
+    let bot = telegram.dispatch(config.clone(), backend).await;
+
+    telegram.send_text("/backend openai");
+    telegram.wait_for("SendMessage", 1).await;
+    telegram.send_text("What is the capital of France?");
+    telegram.wait_for("EditMessageText", 1).await;
+    bot.stop().await;
+
+    assert_eq!(
+        telegram.calls_to("SendMessage")[0].param("text"),
+        "Your requests are now answered by openai."
+    );
+    assert_eq!(openai.requests().len(), 1);
+    assert_eq!(sent_body(&openai, 0)["stream"], true);
+    let edits = telegram.calls_to("EditMessageText");
+    assert!(edits.last().unwrap().param("text").contains("Paris."));
+
+    let db = config.database.lock().await;
+    let settings = UserSettings::find_or_default(USER_ID, &db).await.unwrap();
+    assert_eq!(settings.backend(), Some(BackendKind::OpenAi));
+}
+
+#[tokio::test]
+async fn test_unconfigured_openai_falls_back_to_gemini() {
+    let config = test_config(NO_SERVER, &["flash"]).await;
+    assert_eq!(
+        config.backend(Some(BackendKind::OpenAi)),
+        BackendKind::Gemini
+    );
+    assert_eq!("OpenAI".parse::<BackendKind>(), Ok(BackendKind::OpenAi));
+    assert!("claude".parse::<BackendKind>().is_err());
+}
@@ -0,0 +1,66 @@
+use crate::gemini::request::RequestBuilder;
+use crate::gemini::services::{generate_request, HistoryTurn};
+use crate::models::gemini::{FunctionDeclaration, GenerationConfig, Part, Tool};
+use crate::tests::support::assert_json_snapshot;
+use serde_json::json;
+use std::sync::Arc;
+
+#[test]
+fn test_request_without_history() {
+    let request = generate_request(&Arc::from(None), &Arc::from(None), "Hello", &[]).build();
+
+    assert_json_snapshot("request_without_history", &request);
+}
+
+#[test]
+fn test_request_with_history_and_attachments() {
+    let mut turn = HistoryTurn::new(
+        String::from("What is in this picture?"),
+        String::from("A cat."),
+    );
+    turn.attachments = vec![Part::inline_data("image/jpeg", b"jpeg bytes")];
+    let history = Arc::from(Some(vec![turn]));
+    let instructions = Arc::from(Some(vec!["be extra precise", "answer briefly"]));
+
+    let request = generate_request(
+        &history,
+        &instructions,
+        "And this document?",
+        &[Part::file_data(
+            "application/pdf",
+            "https://generativelanguage.googleapis.com/v1beta/files/abc",
+        )],
+    )
+    .build();
+
+    assert_json_snapshot("request_with_history_and_attachments", &request);
+}
+
+#[test]
+fn test_builder_sets_every_field() {
+    let request = RequestBuilder::new()
+        .system_instruction("Be brief.")
+        .user("What is 2 + 2?", &[])
+        .generation_config(GenerationConfig {
+            temperature: Some(0.5),
+            max_output_tokens: Some(1024),
+            stop_sequences: vec![String::from("END")],
+            ..Default::default()
+        })
+        .safety_setting("HARM_CATEGORY_HARASSMENT", "BLOCK_ONLY_HIGH")
+        .tool(Tool::functions(vec![FunctionDeclaration {
+            name: String::from("calculate"),
+            description: String::from("Evaluates an expression."),
+            parameters: json!({
+                "type": "object",
+                "properties": {"expression": {"type": "string"}},
+                "required": ["expression"]
+            }),
+        }]))
+        .tool(Tool::google_search())
+        .function_calling_mode("AUTO")
+        .cached_content("cachedContents/abc")
+        .build();
+
+    assert_json_snapshot("request_builder_all_fields", &request);
+}
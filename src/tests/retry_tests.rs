@@ -0,0 +1,209 @@
+use crate::gemini::error::GeminiError;
+use crate::gemini::retry::{parse_retry_delay, RetryPolicy};
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const OK_BODY: &str =
+    r#"{"candidates": [{"content": {"parts": [{"text": "pong"}], "role": "model"}}]}"#;
+const UNAVAILABLE_BODY: &str =
+    r#"{"error": {"code": 503, "message": "The model is overloaded.", "status": "UNAVAILABLE"}}"#;
+
+fn quota_body(retry_delay: &str) -> String {
+    format!(
+        r#"{{"error": {{"code": 429, "message": "Resource exhausted", "status": "RESOURCE_EXHAUSTED",
+            "details": [{{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "{}"}}]}}}}"#,
+        retry_delay
+    )
+}
+
+#[test]
+fn test_retry_delay_bounds() {
+    let policy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(250),
+    };
+
+    for attempt in 0..5 {
+        let delay = policy.delay(attempt, None).unwrap();
+        assert!(delay <= Duration::from_millis(250));
+    }
+    assert!(policy.delay(0, None).unwrap() <= Duration::from_millis(100));
+    assert_eq!(
+        policy.delay(0, Some(Duration::from_millis(200))),
+        Some(Duration::from_millis(200))
+    );
+    assert_eq!(policy.delay(0, Some(Duration::from_secs(30))), None);
+}
+
+#[test]
+fn test_parse_retry_delay() {
+    assert_eq!(parse_retry_delay("34s"), Some(Duration::from_secs(34)));
+    assert_eq!(parse_retry_delay("0.5s"), Some(Duration::from_millis(500)));
+    assert_eq!(parse_retry_delay("soon"), None);
+}
+
+#[tokio::test]
+async fn test_retries_until_primary_answers() {
+    let server = MockServer::start(vec![
+        MockResponse::json(503, UNAVAILABLE_BODY),
+        MockResponse::json(503, UNAVAILABLE_BODY),
+        MockResponse::json(200, OK_BODY),
+    ])
+    .await;
+    let config = test_config(&server.url, &["primary", "fallback"]).await;
+
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reply.text, "pong");
+    assert_eq!(reply.model, "primary");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(
+        |x| x.method == "POST" && x.path.starts_with("/v1beta/models/primary:generateContent")
+    ));
+    assert!(requests[0].headers.contains(&(
+        String::from("content-type"),
+        String::from("application/json")
+    )));
+    assert!(requests[0].body.contains("ping"));
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+}
+
+#[tokio::test]
+async fn test_falls_back_to_next_model() {
+    let server = MockServer::start(vec![
+        MockResponse::json(429, &quota_body("0.01s")),
+        MockResponse::json(429, &quota_body("0.01s")),
+        MockResponse::json(429, &quota_body("0.01s")),
+        MockResponse::json(200, OK_BODY),
+    ])
+    .await;
+    let config = test_config(&server.url, &["primary", "fallback"]).await;
+
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reply.model, "fallback");
+    let paths: Vec<String> = server.requests().into_iter().map(|x| x.path).collect();
+    assert_eq!(paths.len(), 4);
+    assert!(paths[3].starts_with("/v1beta/models/fallback:generateContent"));
+}
+
+#[tokio::test]
+async fn test_honors_retry_info_delay() {
+    let server = MockServer::start(vec![
+        MockResponse::json(429, &quota_body("0.15s")),
+        MockResponse::json(200, OK_BODY),
+    ])
+    .await;
+    let config = test_config(&server.url, &["primary"]).await;
+
+    let started = Instant::now();
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await;
+
+    assert!(reply.is_ok());
+    assert!(started.elapsed() >= Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn test_long_retry_info_delay_skips_to_fallback() {
+    let server = MockServer::start(vec![
+        MockResponse::json(429, &quota_body("60s")),
+        MockResponse::json(200, OK_BODY),
+    ])
+    .await;
+    let config = test_config(&server.url, &["primary", "fallback"]).await;
+
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reply.model, "fallback");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_client_errors_are_not_retried() {
+    let server = MockServer::start(vec![MockResponse::json(
+        400,
+        r#"{"error": {"code": 400, "message": "Invalid argument", "status": "INVALID_ARGUMENT"}}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["primary", "fallback"]).await;
+
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await;
+
+    match reply {
+        Err(GeminiError::HttpStatus { status, message }) => {
+            assert_eq!(status, 400);
+            assert_eq!(message, "Invalid argument");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_gives_up_after_all_models_fail() {
+    let server = MockServer::start(vec![]).await;
+    let config = test_config(&server.url, &["primary", "fallback"]).await;
+
+    let reply = query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await;
+
+    assert!(matches!(
+        reply,
+        Err(GeminiError::HttpStatus { status: 500, .. })
+    ));
+    assert_eq!(server.requests().len(), 6);
+}
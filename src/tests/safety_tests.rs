@@ -0,0 +1,223 @@
+use crate::bot::bot_logic::generate_response;
+use crate::bot::safety::{describe, request_settings, set_threshold};
+use crate::gemini::backend::{Backend, GeminiBackend};
+use crate::gemini::error::GeminiError;
+use crate::gemini::services::{parse_reply, query_gemini_api, GenerateOptions};
+use crate::models::chat_settings::ChatSettings;
+use crate::models::gemini::SafetySetting;
+use crate::tests::fake_gemini::{FakeGemini, GeminiMethod};
+use crate::tests::fake_telegram::FakeTelegram;
+use crate::tests::support::{
+    setup_test_database, telegram_message, test_config, MockResponse, MockServer,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use teloxide::types::Message;
+
+#[test]
+fn test_set_threshold() {
+    let mut settings = ChatSettings::new(-100);
+
+    set_threshold(&mut settings, "harassment high", None).unwrap();
+    set_threshold(&mut settings, "HARM_CATEGORY_HATE_SPEECH BLOCK_NONE", None).unwrap();
+    assert_eq!(
+        settings.safety_settings,
+        vec![
+            SafetySetting {
+                category: "HARM_CATEGORY_HARASSMENT".to_string(),
+                threshold: "BLOCK_ONLY_HIGH".to_string(),
+            },
+            SafetySetting {
+                category: "HARM_CATEGORY_HATE_SPEECH".to_string(),
+                threshold: "BLOCK_NONE".to_string(),
+            },
+        ]
+    );
+    let text = describe(&settings, None);
+    assert!(text.contains("harassment: high\nhate: none\nsexual: default"));
+
+    // Setting a category again replaces it, default removes it
+    set_threshold(&mut settings, "harassment low", None).unwrap();
+    set_threshold(&mut settings, "hate default", None).unwrap();
+    assert_eq!(settings.safety_settings.len(), 1);
+    assert_eq!(settings.safety_settings[0].threshold, "BLOCK_LOW_AND_ABOVE");
+
+    set_threshold(&mut settings, "all medium", None).unwrap();
+    assert_eq!(settings.safety_settings.len(), 5);
+
+    set_threshold(&mut settings, "reset", None).unwrap();
+    assert!(settings.safety_settings.is_empty());
+
+    assert!(set_threshold(&mut settings, "violence high", None).is_err());
+    assert!(set_threshold(&mut settings, "hate loose", None).is_err());
+    assert!(set_threshold(&mut settings, "hate", None).is_err());
+}
+
+#[test]
+fn test_private_limit() {
+    let mut settings = ChatSettings::new(1);
+    let limit = Some("BLOCK_ONLY_HIGH");
+
+    assert!(set_threshold(&mut settings, "dangerous none", limit).is_err());
+    assert!(set_threshold(&mut settings, "all off", limit).is_err());
+    assert!(settings.safety_settings.is_empty());
+
+    set_threshold(&mut settings, "dangerous high", limit).unwrap();
+    set_threshold(&mut settings, "sexual low", limit).unwrap();
+    assert_eq!(settings.safety_settings.len(), 2);
+
+    // Default is the limit, not the looser API default
+    let text = set_threshold(&mut settings, "sexual default", limit).unwrap();
+    assert!(text.contains("harassment: high\nhate: high\nsexual: high"));
+}
+
+#[test]
+fn test_request_settings() {
+    let mut settings = ChatSettings::new(1);
+    assert!(request_settings(&settings, None).is_empty());
+
+    // Every category is sent with a limit, unset ones at the limit
+    let sent = request_settings(&settings, Some("BLOCK_ONLY_HIGH"));
+    assert_eq!(sent.len(), 5);
+    assert!(sent.iter().all(|x| x.threshold == "BLOCK_ONLY_HIGH"));
+
+    set_threshold(&mut settings, "hate low", None).unwrap();
+    set_threshold(&mut settings, "dangerous off", None).unwrap();
+    let sent = request_settings(&settings, Some("BLOCK_MEDIUM_AND_ABOVE"));
+    let threshold = |category: &str| {
+        sent.iter()
+            .find(|x| x.category == category)
+            .map(|x| x.threshold.as_str())
+    };
+    assert_eq!(
+        threshold("HARM_CATEGORY_HATE_SPEECH"),
+        Some("BLOCK_LOW_AND_ABOVE")
+    );
+    // Stored before the limit was tightened
+    assert_eq!(
+        threshold("HARM_CATEGORY_DANGEROUS_CONTENT"),
+        Some("BLOCK_MEDIUM_AND_ABOVE")
+    );
+    assert_eq!(request_settings(&settings, None).len(), 2);
+}
+
+#[tokio::test]
+async fn test_private_chat_request_carries_limit() {
+    let gemini = FakeGemini::start().await;
+    gemini.stream(&["pong"]);
+    let telegram = FakeTelegram::start().await;
+    let config = test_config(&gemini.url, &["flash"]).await;
+    let backend: Backend = Arc::new(GeminiBackend);
+
+    // The user never used /safety in their private chat
+    let question: Message = serde_json::from_str(&telegram_message(1, "ping")).unwrap();
+    generate_response(
+        telegram.bot(),
+        &question,
+        String::from("ping"),
+        Vec::new(),
+        config,
+        &backend,
+    )
+    .await
+    .unwrap();
+
+    let requests = gemini.requests_to(GeminiMethod::StreamGenerateContent);
+    let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+    let sent = body["safetySettings"].as_array().unwrap();
+    assert_eq!(sent.len(), 5);
+    assert!(sent.iter().all(|x| x["threshold"] == "BLOCK_ONLY_HIGH"));
+}
+
+#[test]
+fn test_blocked_category_is_reported() {
+    let body = r#"{
+        "candidates": [{
+            "finishReason": "SAFETY",
+            "safetyRatings": [
+                {"category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW"},
+                {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH"},
+                {"category": "HARM_CATEGORY_HATE_SPEECH", "probability": "NEGLIGIBLE"}
+            ]
+        }]
+    }"#;
+    match parse_reply(body, "gemini-2.0-flash") {
+        Err(GeminiError::SafetyBlocked { category, .. }) => {
+            assert_eq!(category.as_deref(), Some("HARM_CATEGORY_DANGEROUS_CONTENT"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let body = r#"{
+        "promptFeedback": {
+            "blockReason": "SAFETY",
+            "safetyRatings": [
+                {"category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "probability": "HIGH"},
+                {"category": "HARM_CATEGORY_HARASSMENT", "probability": "MEDIUM", "blocked": true}
+            ]
+        }
+    }"#;
+    match parse_reply(body, "gemini-2.0-flash") {
+        Err(GeminiError::SafetyBlocked { reason, category }) => {
+            assert_eq!(reason, "SAFETY");
+            assert_eq!(category.as_deref(), Some("HARM_CATEGORY_HARASSMENT"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_chat_settings_persist() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await;
+
+    assert!(ChatSettings::find_or_default(-100, &db)
+        .await?
+        .safety_settings
+        .is_empty());
+
+    let mut settings = ChatSettings::new(-100);
+    set_threshold(&mut settings, "civic none", None).unwrap();
+    settings.insert(&db).await?;
+    settings.insert(&db).await?;
+
+    let found = ChatSettings::find_by_chat_id(-100, &db).await?.unwrap();
+    assert_eq!(found.safety_settings, settings.safety_settings);
+
+    ChatSettings::delete_by_chat_id(-100, &db).await?;
+    assert!(ChatSettings::find_by_chat_id(-100, &db).await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_safety_settings_are_sent() {
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let mut settings = ChatSettings::new(-100);
+    set_threshold(&mut settings, "harassment high", None).unwrap();
+    let options = GenerateOptions {
+        safety_settings: settings.safety_settings,
+        ..Default::default()
+    };
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+    assert_eq!(
+        body["safetySettings"],
+        json!([{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH"}])
+    );
+}
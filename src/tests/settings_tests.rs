@@ -0,0 +1,105 @@
+use crate::bot::settings::{apply_choice, describe, set_parameter};
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::models::user_settings::UserSettings;
+use crate::tests::support::{setup_test_database, test_config, MockResponse, MockServer};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+#[test]
+fn test_presets_and_reset() {
+    let mut settings = UserSettings::new(1);
+    assert!(settings.generation_config().is_none());
+    assert!(describe(&settings).contains("API defaults"));
+
+    assert!(apply_choice(&mut settings, "precise"));
+    assert_eq!(settings.preset.as_deref(), Some("precise"));
+    assert_eq!(settings.temperature, Some(0.2));
+    assert_eq!(settings.top_k, Some(20));
+    assert!(!apply_choice(&mut settings, "unknown"));
+
+    // Tuning a sampling value leaves the preset
+    set_parameter(&mut settings, "temperature 0.5").unwrap();
+    assert_eq!(settings.preset, None);
+    assert_eq!(settings.temperature, Some(0.5));
+
+    assert!(apply_choice(&mut settings, "reset"));
+    assert!(settings.generation_config().is_none());
+}
+
+#[test]
+fn test_set_parameter_validation() {
+    let mut settings = UserSettings::new(1);
+
+    assert!(set_parameter(&mut settings, "temperature 2.5").is_err());
+    assert!(set_parameter(&mut settings, "top_p abc").is_err());
+    assert!(set_parameter(&mut settings, "top_k").is_err());
+    assert!(set_parameter(&mut settings, "colour blue").is_err());
+    assert!(set_parameter(&mut settings, "stop a,b,c,d,e,f").is_err());
+    assert!(settings.generation_config().is_none());
+
+    set_parameter(&mut settings, "max_tokens 512").unwrap();
+    set_parameter(&mut settings, "stop END, ###").unwrap();
+    set_parameter(&mut settings, "thinking_budget 0").unwrap();
+    assert_eq!(settings.stop_sequences, vec!["END", "###"]);
+
+    let config = settings.generation_config().unwrap();
+    assert_eq!(config.max_output_tokens, Some(512));
+    assert_eq!(config.thinking_config.unwrap().thinking_budget, Some(0));
+
+    set_parameter(&mut settings, "max_tokens default").unwrap();
+    assert_eq!(settings.max_output_tokens, None);
+}
+
+#[tokio::test]
+async fn test_generation_settings_persist() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await;
+
+    let mut settings = UserSettings::new(1);
+    apply_choice(&mut settings, "creative");
+    set_parameter(&mut settings, "stop END").unwrap();
+    set_parameter(&mut settings, "thinking_budget -1").unwrap();
+    settings.insert(&db).await?;
+
+    let found = UserSettings::find_by_user_id(1, &db).await?.unwrap();
+    assert_eq!(found.preset.as_deref(), Some("creative"));
+    assert_eq!(found.temperature, Some(1.4));
+    assert_eq!(found.top_k, Some(64));
+    assert_eq!(found.stop_sequences, vec!["END"]);
+    assert_eq!(found.thinking_budget, Some(-1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generation_config_is_sent() {
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let mut settings = UserSettings::new(1);
+    apply_choice(&mut settings, "balanced");
+    set_parameter(&mut settings, "max_tokens 256").unwrap();
+    let options = GenerateOptions {
+        generation_config: settings.generation_config(),
+        ..Default::default()
+    };
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+    assert_eq!(
+        body["generationConfig"],
+        json!({"temperature": 0.7, "topP": 0.95, "topK": 40, "maxOutputTokens": 256})
+    );
+}
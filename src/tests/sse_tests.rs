@@ -0,0 +1,43 @@
+use crate::gemini::sse::SseParser;
+use crate::models::gemini::{GeminiResponse, PartData};
+
+#[test]
+fn test_sse_events_split_across_chunks() {
+    let mut parser = SseParser::new();
+
+    let events =
+        parser.push(b"data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Hel");
+    assert!(events.is_empty());
+
+    let events = parser.push(b"lo\"}]}}]}\r\n\r\ndata: {\"candidates\": []}\r\n\r\n");
+    assert_eq!(events.len(), 2);
+
+    let response = serde_json::from_str::<GeminiResponse>(&events[0]).unwrap();
+    assert!(matches!(
+        &response.candidates[0].content.parts[0].data,
+        PartData::Text(text) if text == "Hello"
+    ));
+    assert_eq!(events[1], "{\"candidates\": []}");
+
+    assert!(parser.finish().is_none());
+}
+
+#[test]
+fn test_sse_multibyte_char_split_across_chunks() {
+    let mut parser = SseParser::new();
+    let payload = "data: \"سلام\"\n\n".as_bytes();
+
+    assert!(parser.push(&payload[..9]).is_empty());
+    let events = parser.push(&payload[9..]);
+
+    assert_eq!(events, vec!["\"سلام\"".to_string()]);
+}
+
+#[test]
+fn test_sse_ignores_comments_and_keeps_trailing_event() {
+    let mut parser = SseParser::new();
+
+    assert!(parser.push(b": keep-alive\n\n").is_empty());
+    assert!(parser.push(b"data: last").is_empty());
+    assert_eq!(parser.finish(), Some("last".to_string()));
+}
@@ -0,0 +1,474 @@
+use crate::app::config::{
+    AppConfig, DEFAULT_CACHE_MIN_TOKENS, DEFAULT_CACHE_TTL_SECS, DEFAULT_HISTORY_TOKEN_BUDGET,
+    DEFAULT_PRIVATE_SAFETY_LIMIT,
+};
+use crate::app::http::HttpSettings;
+use crate::db::database::Database;
+use crate::gemini::backend::{BackendKind, GenerativeBackend, Prompt};
+use crate::gemini::error::GeminiError;
+use crate::gemini::keys::{KeyPool, KeySelection};
+use crate::gemini::retry::RetryPolicy;
+use crate::gemini::services::{GeminiReply, GenerateOptions, HistoryTurn, ReplyPart};
+use crate::models::gemini::Content;
+use crate::tools::ToolRegistry;
+use crate::utils::crypto::MasterKey;
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::migrate::Migrator;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Master key of `test_config`, 32 zero bytes.
+pub const TEST_MASTER_KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+/// Pause between the chunks of a streamed response, long enough for the client to read them one by one.
+const CHUNK_PAUSE: Duration = Duration::from_millis(10);
+
+/// A canned HTTP response served by `MockServer`.
+#[derive(Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Wait before answering, to simulate a slow or stuck server.
+    pub delay: Duration,
+    /// Send the body in these pieces with a short pause between them, like a streamed response.
+    pub chunks: Vec<String>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string(),
+            delay: Duration::ZERO,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// An error in the format of Google APIs, `{"error": {"code", "message"}}`.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(
+            status,
+            &serde_json::json!({ "error": { "code": status, "message": message } }).to_string(),
+        )
+    }
+
+    /// A 200 streaming the events as server-sent events, each one a `data:` line sent on its own.
+    pub fn sse(events: &[&str]) -> Self {
+        let chunks: Vec<String> = events.iter().map(|x| format!("data: {}\n\n", x)).collect();
+        Self {
+            content_type: "text/event-stream",
+            body: chunks.concat(),
+            chunks,
+            ..Self::json(200, "")
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A request received by `MockServer`.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// Value of the header, names are compared in lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// No request may leave the test, nothing listens on the discard port.
+pub const NO_SERVER: &str = "http://127.0.0.1:9";
+
+/// A text message of user 7 in their private chat with the bot, as JSON of the Bot API.
+pub fn telegram_message(message_id: i32, text: &str) -> String {
+    format!(
+        r#"{{"message_id": {}, "date": 1700000000, "text": "{}",
+            "chat": {{"id": 7, "type": "private", "first_name": "Ann"}},
+            "from": {{"id": 7, "is_bot": false, "first_name": "Ann"}}}}"#,
+        message_id, text
+    )
+}
+
+/// Decides the response to a request received by a local server.
+pub type Handler = Arc<dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync>;
+
+/// Minimal local HTTP server answering every request with the next scripted response.
+/// Once the script runs out it keeps answering with a 500.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    script: Arc<Mutex<VecDeque<MockResponse>>>,
+}
+
+impl MockServer {
+    pub async fn start(script: Vec<MockResponse>) -> Self {
+        let script = Arc::new(Mutex::new(VecDeque::from(script)));
+        let pending = script.clone();
+        let (url, requests) = listen(Arc::new(move |_| {
+            pending
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| MockResponse::error(500, "script exhausted"))
+        }))
+        .await;
+
+        Self {
+            url,
+            requests,
+            script,
+        }
+    }
+
+    /// Adds a response to the end of the script, for responses that need the server url.
+    pub fn push(&self, response: MockResponse) {
+        self.script.lock().unwrap().push_back(response);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Starts a server on a free local port answering with `handler`.
+/// Returns its url and the requests it receives.
+pub async fn listen(handler: Handler) -> (String, Arc<Mutex<Vec<RecordedRequest>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                if let Some((request, mut stream)) = read_request(stream).await {
+                    let response = handler(&request);
+                    recorded.lock().unwrap().push(request);
+                    write_response(&mut stream, &response).await;
+                }
+            });
+        }
+    });
+
+    (url, requests)
+}
+
+async fn read_request(mut tcp: TcpStream) -> Option<(RecordedRequest, TcpStream)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        let read = tcp.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let chunked = headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value.contains("chunked"));
+    let body = if chunked {
+        // Streamed bodies, e.g. files uploaded by teloxide, end with an empty chunk
+        let mut body = buffer.split_off(header_end);
+        loop {
+            if let Some(decoded) = decode_chunked(&body) {
+                break decoded;
+            }
+            let read = tcp.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+    } else {
+        let content_length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        while buffer.len() < header_end + content_length {
+            let read = tcp.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+        buffer.split_off(header_end)
+    };
+    let body = String::from_utf8_lossy(&body).to_string();
+
+    Some((
+        RecordedRequest {
+            method,
+            path,
+            headers,
+            body,
+        },
+        tcp,
+    ))
+}
+
+/// The body of a complete chunked transfer, None while its last chunk is still missing.
+fn decode_chunked(data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut rest = data;
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&rest[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        if rest.len() < size + 2 {
+            return None;
+        }
+        body.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, response: &MockResponse) {
+    tokio::time::sleep(response.delay).await;
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    if !response.chunks.is_empty() {
+        _ = stream.write_all(head.as_bytes()).await;
+        for chunk in &response.chunks {
+            _ = stream.write_all(chunk.as_bytes()).await;
+            _ = stream.flush().await;
+            tokio::time::sleep(CHUNK_PAUSE).await;
+        }
+    } else {
+        head.push_str(&response.body);
+        _ = stream.write_all(head.as_bytes()).await;
+    }
+    _ = stream.shutdown().await;
+}
+
+/// A prompt received by `MockBackend`.
+#[derive(Debug, Clone)]
+pub struct RecordedPrompt {
+    pub query: String,
+    pub history: Vec<HistoryTurn>,
+    pub options: GenerateOptions,
+    pub streamed: bool,
+}
+
+/// `GenerativeBackend` answering every prompt with the next scripted reply, without any network.
+/// Once the script runs out it answers with `EmptyCandidates`, token counts that were not
+/// scripted fail so the estimate is used.
+#[derive(Default)]
+pub struct MockBackend {
+    replies: Mutex<VecDeque<Result<String, GeminiError>>>,
+    token_counts: Mutex<VecDeque<i64>>,
+    prompts: Mutex<Vec<RecordedPrompt>>,
+}
+
+impl MockBackend {
+    pub fn new(replies: &[&str]) -> Self {
+        let backend = Self::default();
+        for reply in replies {
+            backend.push(Ok(reply.to_string()));
+        }
+        backend
+    }
+
+    pub fn push(&self, reply: Result<String, GeminiError>) {
+        self.replies.lock().unwrap().push_back(reply);
+    }
+
+    pub fn push_token_count(&self, count: i64) {
+        self.token_counts.lock().unwrap().push_back(count);
+    }
+
+    pub fn prompts(&self) -> Vec<RecordedPrompt> {
+        self.prompts.lock().unwrap().clone()
+    }
+
+    fn answer(
+        &self,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        streamed: bool,
+    ) -> Result<GeminiReply, GeminiError> {
+        self.prompts.lock().unwrap().push(RecordedPrompt {
+            query: prompt.query.to_string(),
+            history: prompt.history.as_deref().unwrap_or_default().to_vec(),
+            options: options.clone(),
+            streamed,
+        });
+        let text = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Err(GeminiError::EmptyCandidates))?;
+
+        Ok(GeminiReply {
+            parts: vec![ReplyPart::Text(text.clone())],
+            text,
+            model: options.model.clone().unwrap_or(String::from("mock")),
+            usage_metadata: None,
+            model_version: None,
+            sources: Vec::new(),
+            search_queries: Vec::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl GenerativeBackend for MockBackend {
+    async fn generate(
+        &self,
+        _config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+    ) -> Result<GeminiReply, GeminiError> {
+        self.answer(prompt, options, false)
+    }
+
+    /// Sends the reply word by word.
+    async fn stream(
+        &self,
+        _config: &Arc<AppConfig>,
+        prompt: Prompt<'_>,
+        options: &GenerateOptions,
+        updates: mpsc::UnboundedSender<String>,
+    ) -> Result<GeminiReply, GeminiError> {
+        let reply = self.answer(prompt, options, true)?;
+        let mut text = String::new();
+        for word in reply.text.split_inclusive(' ') {
+            text.push_str(word);
+            _ = updates.send(text.clone());
+        }
+        Ok(reply)
+    }
+
+    async fn count_tokens(
+        &self,
+        _config: &Arc<AppConfig>,
+        _options: &GenerateOptions,
+        _contents: Vec<Content>,
+    ) -> Result<i64, GeminiError> {
+        self.token_counts
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(GeminiError::MalformedBody(String::from(
+                "no token count scripted",
+            )))
+    }
+}
+
+pub async fn setup_test_database() -> Database {
+    let db = Database::new("sqlite::memory:").await.unwrap();
+    MIGRATOR.run(db.pool()).await.unwrap();
+    db
+}
+
+/// Config pointing at a local server, with retry delays short enough for tests.
+pub async fn test_config(server_url: &str, models: &[&str]) -> Arc<AppConfig> {
+    Arc::new(AppConfig {
+        gemini_keys: KeyPool::new(
+            vec![String::from("test-key")],
+            KeySelection::RoundRobin,
+            Duration::from_secs(60),
+        ),
+        gemini_base_url: format!("{}/v1beta", server_url),
+        vertex: None,
+        http_client: HttpSettings::default().build_client().unwrap(),
+        gemini_models: models.iter().map(|x| x.to_string()).collect(),
+        selectable_models: models.iter().map(|x| x.to_string()).collect(),
+        retry_policy: RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(200),
+        },
+        openai: None,
+        default_backend: BackendKind::Gemini,
+        history_token_budget: DEFAULT_HISTORY_TOKEN_BUDGET,
+        master_key: Some(MasterKey::from_base64(TEST_MASTER_KEY).unwrap()),
+        cache_ttl_secs: DEFAULT_CACHE_TTL_SECS,
+        cache_min_tokens: DEFAULT_CACHE_MIN_TOKENS,
+        private_safety_limit: String::from(DEFAULT_PRIVATE_SAFETY_LIMIT),
+        log_content: false,
+        tools: ToolRegistry::with_builtin_tools(),
+        database: tokio::sync::Mutex::new(setup_test_database().await),
+    })
+}
+
+/// Compares `value`, serialized as pretty JSON, with `src/tests/snapshots/<name>.json`.
+/// Run the tests with `UPDATE_SNAPSHOTS=1` to write the current output instead.
+pub fn assert_json_snapshot<T: Serialize>(name: &str, value: &T) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tests/snapshots")
+        .join(format!("{}.json", name));
+    let actual = format!("{}\n", serde_json::to_string_pretty(value).unwrap());
+
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Missing snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(actual, expected, "Snapshot {} does not match", name);
+}
@@ -0,0 +1,334 @@
+use crate::gemini::services::{query_gemini_api, GenerateOptions};
+use crate::models::message::Message;
+use crate::models::user_settings::UserSettings;
+use crate::tests::support::{test_config, MockResponse, MockServer};
+use crate::tools::{calculator, units, ToolContext};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::sync::Arc;
+
+fn decimal(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+#[test]
+fn test_calculator_is_exact_and_respects_precedence() {
+    assert_eq!(calculator::evaluate("0.1 + 0.2").unwrap(), decimal("0.3"));
+    assert_eq!(calculator::evaluate("2 + 3 * 4").unwrap(), decimal("14"));
+    assert_eq!(calculator::evaluate("(2 + 3) * 4").unwrap(), decimal("20"));
+    assert_eq!(calculator::evaluate("-2^2").unwrap(), decimal("-4"));
+    assert_eq!(calculator::evaluate("2^3^2").unwrap(), decimal("512"));
+    assert_eq!(calculator::evaluate("17 % 5").unwrap(), decimal("2"));
+    assert_eq!(
+        calculator::evaluate("sqrt(16) / 8").unwrap(),
+        decimal("0.5")
+    );
+}
+
+#[test]
+fn test_calculator_errors() {
+    assert_eq!(
+        calculator::evaluate("1 / 0").unwrap_err(),
+        "Division by zero"
+    );
+    assert!(calculator::evaluate("2 +").is_err());
+    assert!(calculator::evaluate("(1 + 2").is_err());
+    assert!(calculator::evaluate("foo(2)").is_err());
+    assert!(calculator::evaluate("ln(-1)").is_err());
+    assert!(calculator::evaluate("1 2").is_err());
+}
+
+#[test]
+fn test_unit_conversion() {
+    assert_eq!(
+        units::convert(decimal("1"), "mi", "km").unwrap(),
+        decimal("1.609344")
+    );
+    assert_eq!(
+        units::convert(decimal("2"), "pounds", "kg").unwrap(),
+        decimal("0.90718474")
+    );
+    assert_eq!(
+        units::convert(decimal("1"), "GiB", "MiB").unwrap(),
+        decimal("1024")
+    );
+    assert_eq!(
+        units::convert(decimal("100"), "C", "F").unwrap(),
+        decimal("212")
+    );
+    assert_eq!(
+        units::convert(decimal("0"), "K", "Celsius").unwrap(),
+        decimal("-273.15")
+    );
+    assert!(units::convert(decimal("1"), "km", "kg").is_err());
+    assert!(units::convert(decimal("1"), "parsec", "km").is_err());
+}
+
+#[tokio::test]
+async fn test_registry_declares_and_runs_tools() {
+    let config = test_config("http://localhost", &["flash"]).await;
+    let context = ToolContext {
+        user_id: 1,
+        config: config.clone(),
+    };
+
+    let declarations = config.tools.declarations();
+    let names: Vec<&str> = declarations.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "calculate",
+            "current_datetime",
+            "convert_units",
+            "search_history"
+        ]
+    );
+
+    let result = config
+        .tools
+        .execute("calculate", &json!({"expression": "6 * 7"}), &context)
+        .await;
+    assert_eq!(result["result"]["value"], "42");
+
+    let result = config.tools.execute("missing", &json!({}), &context).await;
+    assert!(result["error"].is_string());
+
+    let result = config
+        .tools
+        .execute("calculate", &json!({}), &context)
+        .await;
+    assert!(result["error"].is_string());
+}
+
+#[tokio::test]
+async fn test_datetime_uses_user_timezone() {
+    let config = test_config("http://localhost", &["flash"]).await;
+    let context = ToolContext {
+        user_id: 1,
+        config: config.clone(),
+    };
+
+    let result = config
+        .tools
+        .execute("current_datetime", &json!({}), &context)
+        .await;
+    assert_eq!(result["result"]["timezone"], "UTC");
+
+    let mut settings = UserSettings::new(1);
+    settings.timezone = Some(String::from("Asia/Tokyo"));
+    settings
+        .insert(&*config.database.lock().await)
+        .await
+        .unwrap();
+
+    let result = config
+        .tools
+        .execute("current_datetime", &json!({}), &context)
+        .await;
+    assert_eq!(result["result"]["timezone"], "Asia/Tokyo");
+    assert!(result["result"]["datetime"]
+        .as_str()
+        .unwrap()
+        .ends_with("+09:00"));
+
+    let result = config
+        .tools
+        .execute(
+            "current_datetime",
+            &json!({"timezone": "Mars/Olympus"}),
+            &context,
+        )
+        .await;
+    assert!(result["error"].is_string());
+}
+
+#[tokio::test]
+async fn test_history_search_only_returns_own_messages() {
+    let config = test_config("http://localhost", &["flash"]).await;
+    {
+        let db = config.database.lock().await;
+        let messages = [
+            (1, "What is the capital of France?", "Paris"),
+            (1, "How tall is the Eiffel tower?", "330 metres"),
+            (2, "Where is the Eiffel tower?", "In Paris"),
+        ];
+        for (i, (sender, question, answer)) in messages.iter().enumerate() {
+            Message::new(
+                *sender,
+                *sender,
+                i as i64,
+                Some(question.to_string()),
+                Some(answer.to_string()),
+                i as i64,
+            )
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+    }
+    let context = ToolContext {
+        user_id: 1,
+        config: config.clone(),
+    };
+
+    let result = config
+        .tools
+        .execute("search_history", &json!({"query": "paris"}), &context)
+        .await;
+    let matches = result["result"]["matches"].as_array().unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0]["answer"], "Paris");
+
+    let result = config
+        .tools
+        .execute("search_history", &json!({"query": "100%"}), &context)
+        .await;
+    assert!(result["result"]["matches"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_function_call_loop() {
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [
+                {"functionCall": {"name": "calculate", "args": {"expression": "1234 * 5678"}}, "thoughtSignature": "c2ln"}
+            ]}}]}"#,
+        ),
+        MockResponse::json(
+            200,
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "It is 7006652."}]}}]}"#,
+        ),
+    ])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let options = GenerateOptions {
+        model: None,
+        tool_user: Some(1),
+        ..Default::default()
+    };
+    let reply = query_gemini_api(
+        "What is 1234 * 5678?",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reply.text, "It is 7006652.");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+
+    let first: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert!(first["tools"][0]["functionDeclarations"].is_array());
+
+    let second: Value = serde_json::from_str(&requests[1].body).unwrap();
+    let contents = second["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 3);
+    assert_eq!(contents[1]["role"], "model");
+    assert_eq!(contents[1]["parts"][0]["functionCall"]["name"], "calculate");
+    assert_eq!(contents[1]["parts"][0]["thoughtSignature"], "c2ln");
+    assert_eq!(contents[2]["role"], "user");
+    assert_eq!(
+        contents[2]["parts"][0]["functionResponse"],
+        json!({"name": "calculate", "response": {"result": {"expression": "1234 * 5678", "value": "7006652"}}})
+    );
+}
+
+#[tokio::test]
+async fn test_function_calls_stop_after_max_rounds() {
+    let call = MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [
+            {"functionCall": {"name": "calculate", "args": {"expression": "1 + 1"}}}
+        ]}}]}"#,
+    );
+    let mut script = vec![call; 5];
+    script.push(MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "2"}]}}]}"#,
+    ));
+    let server = MockServer::start(script).await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let options = GenerateOptions {
+        model: None,
+        tool_user: Some(1),
+        ..Default::default()
+    };
+    let reply = query_gemini_api(
+        "1 + 1?",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reply.text, "2");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 6);
+    assert!(!requests[4].body.contains("toolConfig"));
+    let last: Value = serde_json::from_str(&requests[5].body).unwrap();
+    assert_eq!(last["toolConfig"]["functionCallingConfig"]["mode"], "NONE");
+}
+
+#[tokio::test]
+async fn test_tools_are_not_offered_without_user() {
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &GenerateOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert!(!server.requests()[0].body.contains("functionDeclarations"));
+}
+
+#[tokio::test]
+async fn test_grounding_replaces_local_tools() {
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "pong"}]}}]}"#,
+    )])
+    .await;
+    let config = test_config(&server.url, &["flash"]).await;
+
+    let options = GenerateOptions {
+        model: None,
+        tool_user: Some(1),
+        grounding: true,
+        ..Default::default()
+    };
+    query_gemini_api(
+        "ping",
+        &[],
+        &Arc::from(None),
+        &config,
+        &Arc::from(None),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+    assert_eq!(body["tools"], json!([{"googleSearch": {}}]));
+}
@@ -0,0 +1,60 @@
+use crate::models::user_settings::UserSettings;
+use crate::tests::support::{setup_test_database, test_config};
+
+#[tokio::test]
+async fn test_user_settings_insert_find_update_delete() -> Result<(), sqlx::Error> {
+    let db = setup_test_database().await;
+
+    let found_settings = UserSettings::find_or_default(1, &db).await?;
+    assert_eq!(found_settings.model, None);
+
+    let mut settings = UserSettings::new(1);
+    settings.model = Some("gemini-2.0-flash".to_string());
+    settings.insert(&db).await?;
+
+    let mut found_settings = UserSettings::find_by_user_id(1, &db).await?.unwrap();
+    assert_eq!(found_settings.model, Some("gemini-2.0-flash".to_string()));
+
+    found_settings.model = Some("gemini-2.5-pro".to_string());
+    found_settings.grounding = true;
+    found_settings.update(&db).await?;
+    let found_updated_settings = UserSettings::find_by_user_id(1, &db).await?.unwrap();
+    assert_eq!(
+        found_updated_settings.model,
+        Some("gemini-2.5-pro".to_string())
+    );
+    assert!(found_updated_settings.grounding);
+
+    // Inserting again replaces the row of the same user
+    settings.insert(&db).await?;
+    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM user_settings WHERE user_id = 1")
+        .fetch_one(db.pool())
+        .await?;
+    assert_eq!(count.0, 1);
+
+    UserSettings::delete_by_user_id(1, &db).await?;
+    assert!(UserSettings::find_by_user_id(1, &db).await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_model_chain_starts_with_user_model() {
+    let mut config = test_config("http://localhost", &["flash", "flash-lite"]).await;
+    let config = std::sync::Arc::get_mut(&mut config).unwrap();
+    config.selectable_models = vec!["flash".to_string(), "pro".to_string()];
+
+    assert_eq!(config.model_chain(None), vec!["flash", "flash-lite"]);
+    assert_eq!(
+        config.model_chain(Some("pro")),
+        vec!["pro", "flash", "flash-lite"]
+    );
+    assert_eq!(
+        config.model_chain(Some("flash-lite")),
+        vec!["flash", "flash-lite"]
+    );
+    assert_eq!(
+        config.model_chain(Some("unknown")),
+        vec!["flash", "flash-lite"]
+    );
+}
@@ -0,0 +1,205 @@
+// Calculator tool, evaluates arithmetic with decimal precision instead of f64
+use super::{string_arg, Tool, ToolContext};
+use async_trait::async_trait;
+use rust_decimal::{Decimal, MathematicalOps};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+pub struct Calculator;
+
+#[async_trait]
+impl Tool for Calculator {
+    fn name(&self) -> &'static str {
+        "calculate"
+    }
+
+    fn description(&self) -> &'static str {
+        "Evaluates an arithmetic expression exactly. Supports + - * / % ^, parentheses, the constants pi and e and the functions sqrt, abs, ln, log, exp, sin, cos, tan, floor, ceil and round. Use it for any calculation instead of doing math yourself."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": {
+                    "type": "string",
+                    "description": "The expression, e.g. (1.5 + 2) * sqrt(16) / 3"
+                }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    async fn execute(&self, args: &Value, _context: &ToolContext) -> Result<Value, String> {
+        let expression = string_arg(args, "expression")?;
+        let value = evaluate(expression)?;
+        Ok(json!({ "expression": expression, "value": value.normalize().to_string() }))
+    }
+}
+
+/// Evaluates `expression`, returning a message describing the problem when it is invalid.
+pub fn evaluate(expression: &str) -> Result<Decimal, String> {
+    let mut parser = Parser {
+        chars: expression.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.expression()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("Unexpected '{}'", c)),
+    }
+}
+
+/// Recursive descent parser, `^` binds tighter than unary minus so -2^2 is -4.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Next character that is not whitespace.
+    fn peek(&mut self) -> Option<char> {
+        while matches!(self.chars.get(self.pos), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<Decimal, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value = value.checked_add(self.term()?).ok_or(OVERFLOW)?;
+            } else if self.eat('-') {
+                value = value.checked_sub(self.term()?).ok_or(OVERFLOW)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Decimal, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value = value.checked_mul(self.unary()?).ok_or(OVERFLOW)?;
+            } else if self.eat('/') {
+                let divisor = self.unary()?;
+                if divisor.is_zero() {
+                    return Err(String::from("Division by zero"));
+                }
+                value = value.checked_div(divisor).ok_or(OVERFLOW)?;
+            } else if self.eat('%') {
+                let divisor = self.unary()?;
+                if divisor.is_zero() {
+                    return Err(String::from("Division by zero"));
+                }
+                value = value.checked_rem(divisor).ok_or(OVERFLOW)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Decimal, String> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Decimal, String> {
+        let base = self.primary()?;
+        if !self.eat('^') {
+            return Ok(base);
+        }
+        let exponent = self.unary()?;
+        let result = if exponent.fract().is_zero() {
+            i64::try_from(exponent)
+                .ok()
+                .and_then(|x| base.checked_powi(x))
+        } else {
+            base.checked_powd(exponent)
+        };
+        result.ok_or(String::from("Cannot compute the power"))
+    }
+
+    fn primary(&mut self) -> Result<Decimal, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expression()?;
+                if !self.eat(')') {
+                    return Err(String::from("Missing ')'"));
+                }
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.identifier(),
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Decimal, String> {
+        let start = self.pos;
+        while matches!(self.chars.get(self.pos), Some(c) if c.is_ascii_digit() || *c == '.') {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        Decimal::from_str(&number).map_err(|_| format!("Invalid number {}", number))
+    }
+
+    fn identifier(&mut self) -> Result<Decimal, String> {
+        let start = self.pos;
+        while matches!(self.chars.get(self.pos), Some(c) if c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        let name = name.to_lowercase();
+
+        match name.as_str() {
+            "pi" => return Ok(Decimal::PI),
+            "e" => return Ok(Decimal::E),
+            _ => {}
+        }
+
+        if !self.eat('(') {
+            return Err(format!("Unknown constant {}", name));
+        }
+        let x = self.expression()?;
+        if !self.eat(')') {
+            return Err(String::from("Missing ')'"));
+        }
+
+        let result = match name.as_str() {
+            "sqrt" => x.sqrt(),
+            "abs" => Some(x.abs()),
+            "ln" => x.checked_ln(),
+            "log" => x.checked_log10(),
+            "exp" => x.checked_exp(),
+            "sin" => x.checked_sin(),
+            "cos" => x.checked_cos(),
+            "tan" => x.checked_tan(),
+            "floor" => Some(x.floor()),
+            "ceil" => Some(x.ceil()),
+            "round" => Some(x.round()),
+            _ => return Err(format!("Unknown function {}", name)),
+        };
+        result.ok_or(format!("{}({}) is undefined", name, x))
+    }
+}
+
+const OVERFLOW: &str = "The result is too large";
@@ -0,0 +1,60 @@
+// Current date and time tool
+use super::{Tool, ToolContext};
+use crate::models::user_settings::UserSettings;
+use async_trait::async_trait;
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+pub struct CurrentDateTime;
+
+#[async_trait]
+impl Tool for CurrentDateTime {
+    fn name(&self) -> &'static str {
+        "current_datetime"
+    }
+
+    fn description(&self) -> &'static str {
+        "Returns the current date, time and weekday. Uses the timezone of the user unless another IANA timezone is given."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "timezone": {
+                    "type": "string",
+                    "description": "IANA timezone such as Europe/Berlin, leave empty for the user's own timezone"
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, context: &ToolContext) -> Result<Value, String> {
+        let timezone = match args.get("timezone").and_then(|x| x.as_str()) {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => user_timezone(context).await,
+        };
+        let tz: Tz = timezone
+            .parse()
+            .map_err(|_| format!("Unknown timezone {}", timezone))?;
+
+        let now = Utc::now().with_timezone(&tz);
+        Ok(json!({
+            "datetime": now.to_rfc3339(),
+            "weekday": now.format("%A").to_string(),
+            "timezone": tz.name(),
+        }))
+    }
+}
+
+/// The timezone the user picked with /timezone, UTC otherwise.
+async fn user_timezone(context: &ToolContext) -> String {
+    let db = context.config.database.lock().await;
+    UserSettings::find_by_user_id(context.user_id, &db)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|x| x.timezone)
+        .unwrap_or(String::from("UTC"))
+}
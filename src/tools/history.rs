@@ -0,0 +1,75 @@
+// Conversation history lookup tool
+use super::{string_arg, Tool, ToolContext};
+use crate::models::message::Message;
+use async_trait::async_trait;
+use chrono::DateTime;
+use serde_json::{json, Value};
+
+pub struct HistorySearch;
+
+const DEFAULT_RESULTS: i64 = 5;
+const MAX_RESULTS: i64 = 20;
+/// Long answers are cut so a search can't fill the whole context window.
+const MAX_RESULT_CHARS: usize = 1000;
+
+#[async_trait]
+impl Tool for HistorySearch {
+    fn name(&self) -> &'static str {
+        "search_history"
+    }
+
+    fn description(&self) -> &'static str {
+        "Searches everything the user asked and you answered before, including earlier topics. Use it when the user refers to a past conversation."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {"type": "string", "description": "Word or phrase to look for"},
+                "limit": {"type": "integer", "description": "Maximum number of results, 5 by default"}
+            },
+            "required": ["query"]
+        })
+    }
+
+    async fn execute(&self, args: &Value, context: &ToolContext) -> Result<Value, String> {
+        let query = string_arg(args, "query")?.trim();
+        if query.is_empty() {
+            return Err(String::from("The query is empty"));
+        }
+        let limit = args
+            .get("limit")
+            .and_then(|x| x.as_i64())
+            .unwrap_or(DEFAULT_RESULTS)
+            .clamp(1, MAX_RESULTS);
+
+        let db = context.config.database.lock().await;
+        let messages = Message::search_by_sender(context.user_id, query, limit, &db)
+            .await
+            .map_err(|err| {
+                log::error!("History search failed: {}", err);
+                String::from("The history is not available right now")
+            })?;
+
+        let results: Vec<Value> = messages
+            .iter()
+            .map(|message| {
+                json!({
+                    "date": DateTime::from_timestamp(message.created_at, 0).map(|x| x.to_rfc3339()),
+                    "question": message.content.as_deref().map(truncate),
+                    "answer": message.response.as_deref().map(truncate),
+                })
+            })
+            .collect();
+
+        Ok(json!({ "matches": results }))
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_RESULT_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
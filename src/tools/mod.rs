@@ -0,0 +1,98 @@
+// Tools module, local functions the model can call while answering
+pub mod calculator;
+pub mod datetime;
+pub mod history;
+pub mod units;
+
+use crate::app::config::AppConfig;
+use crate::models::gemini::FunctionDeclaration;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// A function the model can ask us to run.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name the model uses to call the tool.
+    fn name(&self) -> &'static str;
+    /// Tells the model when the tool is useful.
+    fn description(&self) -> &'static str;
+    /// JSON schema (OpenAPI subset) of the arguments.
+    fn parameters(&self) -> Value;
+    /// Runs the tool, the error is reported back to the model so it can correct itself.
+    async fn execute(&self, args: &Value, context: &ToolContext) -> Result<Value, String>;
+}
+
+/// What a tool knows about the request it runs for.
+pub struct ToolContext {
+    /// Telegram id of the user who asked the question.
+    pub user_id: i64,
+    pub config: Arc<AppConfig>,
+}
+
+/// The tools offered to the model.
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self { tools: Vec::new() }
+    }
+
+    /// A registry with every tool shipped with the bot.
+    pub fn with_builtin_tools() -> Self {
+        let mut registry = Self::new();
+        registry.register(calculator::Calculator);
+        registry.register(datetime::CurrentDateTime);
+        registry.register(units::UnitConverter);
+        registry.register(history::HistorySearch);
+        registry
+    }
+
+    /// Adds a tool, replacing a previous one with the same name.
+    pub fn register<T: Tool + 'static>(&mut self, tool: T) {
+        self.tools.retain(|x| x.name() != tool.name());
+        self.tools.push(Arc::new(tool));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// How the tools are described to the model.
+    pub fn declarations(&self) -> Vec<FunctionDeclaration> {
+        self.tools
+            .iter()
+            .map(|tool| FunctionDeclaration {
+                name: tool.name().to_string(),
+                description: tool.description().to_string(),
+                parameters: tool.parameters(),
+            })
+            .collect()
+    }
+
+    /// Runs the requested tool and wraps the outcome into a `functionResponse` payload.
+    pub async fn execute(&self, name: &str, args: &Value, context: &ToolContext) -> Value {
+        let tool = match self.tools.iter().find(|x| x.name() == name) {
+            Some(tool) => tool,
+            None => return json!({ "error": format!("Unknown function {}", name) }),
+        };
+
+        match tool.execute(args, context).await {
+            Ok(result) => json!({ "result": result }),
+            Err(err) => {
+                log::warn!("Tool {} failed: {}", name, err);
+                json!({ "error": err })
+            }
+        }
+    }
+}
+
+/// Reads a required string argument.
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args.get(name)
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| format!("Missing string argument {}", name))
+}
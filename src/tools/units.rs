@@ -0,0 +1,211 @@
+// Unit conversion tool
+use super::{string_arg, Tool, ToolContext};
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+pub struct UnitConverter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quantity {
+    Length,
+    Mass,
+    Volume,
+    Time,
+    Data,
+    Speed,
+    Temperature,
+}
+
+/// Accepted names of a unit and how many base units (metre, kilogram, litre, second, byte, km/h)
+/// one of it is worth. Temperatures are converted separately.
+struct Unit {
+    names: &'static [&'static str],
+    quantity: Quantity,
+    factor: &'static str,
+}
+
+const UNITS: &[Unit] = &[
+    unit(
+        &["mm", "millimeter", "millimetre"],
+        Quantity::Length,
+        "0.001",
+    ),
+    unit(
+        &["cm", "centimeter", "centimetre"],
+        Quantity::Length,
+        "0.01",
+    ),
+    unit(&["m", "meter", "metre"], Quantity::Length, "1"),
+    unit(&["km", "kilometer", "kilometre"], Quantity::Length, "1000"),
+    unit(&["in", "inch", "inches"], Quantity::Length, "0.0254"),
+    unit(&["ft", "foot", "feet"], Quantity::Length, "0.3048"),
+    unit(&["yd", "yard"], Quantity::Length, "0.9144"),
+    unit(&["mi", "mile"], Quantity::Length, "1609.344"),
+    unit(&["nmi", "nautical mile"], Quantity::Length, "1852"),
+    unit(&["mg", "milligram"], Quantity::Mass, "0.000001"),
+    unit(&["g", "gram"], Quantity::Mass, "0.001"),
+    unit(&["kg", "kilogram"], Quantity::Mass, "1"),
+    unit(&["t", "tonne", "ton"], Quantity::Mass, "1000"),
+    unit(&["oz", "ounce"], Quantity::Mass, "0.028349523125"),
+    unit(&["lb", "lbs", "pound"], Quantity::Mass, "0.45359237"),
+    unit(&["st", "stone"], Quantity::Mass, "6.35029318"),
+    unit(
+        &["ml", "milliliter", "millilitre"],
+        Quantity::Volume,
+        "0.001",
+    ),
+    unit(&["l", "liter", "litre"], Quantity::Volume, "1"),
+    unit(
+        &["m3", "cubic meter", "cubic metre"],
+        Quantity::Volume,
+        "1000",
+    ),
+    unit(&["tsp", "teaspoon"], Quantity::Volume, "0.00492892159375"),
+    unit(
+        &["tbsp", "tablespoon"],
+        Quantity::Volume,
+        "0.01478676478125",
+    ),
+    unit(
+        &["floz", "fl oz", "fluid ounce"],
+        Quantity::Volume,
+        "0.0295735295625",
+    ),
+    unit(&["cup"], Quantity::Volume, "0.2365882365"),
+    unit(&["pt", "pint"], Quantity::Volume, "0.473176473"),
+    unit(&["qt", "quart"], Quantity::Volume, "0.946352946"),
+    unit(&["gal", "gallon"], Quantity::Volume, "3.785411784"),
+    unit(&["ms", "millisecond"], Quantity::Time, "0.001"),
+    unit(&["s", "sec", "second"], Quantity::Time, "1"),
+    unit(&["min", "minute"], Quantity::Time, "60"),
+    unit(&["h", "hr", "hour"], Quantity::Time, "3600"),
+    unit(&["d", "day"], Quantity::Time, "86400"),
+    unit(&["wk", "week"], Quantity::Time, "604800"),
+    unit(&["bit"], Quantity::Data, "0.125"),
+    unit(&["b", "byte"], Quantity::Data, "1"),
+    unit(&["kb", "kilobyte"], Quantity::Data, "1000"),
+    unit(&["mb", "megabyte"], Quantity::Data, "1000000"),
+    unit(&["gb", "gigabyte"], Quantity::Data, "1000000000"),
+    unit(&["tb", "terabyte"], Quantity::Data, "1000000000000"),
+    unit(&["kib", "kibibyte"], Quantity::Data, "1024"),
+    unit(&["mib", "mebibyte"], Quantity::Data, "1048576"),
+    unit(&["gib", "gibibyte"], Quantity::Data, "1073741824"),
+    unit(&["tib", "tebibyte"], Quantity::Data, "1099511627776"),
+    unit(&["km/h", "kph", "kmh"], Quantity::Speed, "1"),
+    unit(&["m/s", "mps"], Quantity::Speed, "3.6"),
+    unit(&["mph", "mi/h"], Quantity::Speed, "1.609344"),
+    unit(&["kn", "knot"], Quantity::Speed, "1.852"),
+    unit(&["c", "celsius", "°c"], Quantity::Temperature, "0"),
+    unit(&["f", "fahrenheit", "°f"], Quantity::Temperature, "0"),
+    unit(&["k", "kelvin"], Quantity::Temperature, "0"),
+];
+
+const fn unit(names: &'static [&'static str], quantity: Quantity, factor: &'static str) -> Unit {
+    Unit {
+        names,
+        quantity,
+        factor,
+    }
+}
+
+/// Digits kept in converted values, enough for anything a chat needs.
+const RESULT_DECIMALS: u32 = 10;
+
+#[async_trait]
+impl Tool for UnitConverter {
+    fn name(&self) -> &'static str {
+        "convert_units"
+    }
+
+    fn description(&self) -> &'static str {
+        "Converts a value between units of length, mass, volume, time, digital data, speed or temperature, e.g. 5 mi to km or 100 F to C."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "value": {"type": "number", "description": "The amount to convert"},
+                "from": {"type": "string", "description": "Unit of the value, e.g. km, lb, gal, F, MiB, mph"},
+                "to": {"type": "string", "description": "Unit to convert to"}
+            },
+            "required": ["value", "from", "to"]
+        })
+    }
+
+    async fn execute(&self, args: &Value, _context: &ToolContext) -> Result<Value, String> {
+        let value = args
+            .get("value")
+            .and_then(|x| match x {
+                Value::Number(number) => Decimal::from_str(&number.to_string())
+                    .or_else(|_| Decimal::from_scientific(&number.to_string()))
+                    .ok(),
+                Value::String(text) => Decimal::from_str(text.trim()).ok(),
+                _ => None,
+            })
+            .ok_or(String::from("Missing numeric argument value"))?;
+        let from = string_arg(args, "from")?;
+        let to = string_arg(args, "to")?;
+
+        let converted = convert(value, from, to)?;
+        Ok(json!({ "value": converted.to_string(), "unit": to }))
+    }
+}
+
+/// Converts `value` from one unit to another of the same quantity.
+pub fn convert(value: Decimal, from: &str, to: &str) -> Result<Decimal, String> {
+    let from_unit = find_unit(from)?;
+    let to_unit = find_unit(to)?;
+    if from_unit.quantity != to_unit.quantity {
+        return Err(format!(
+            "Cannot convert {:?} ({}) to {:?} ({})",
+            from_unit.quantity, from, to_unit.quantity, to
+        ));
+    }
+
+    let converted = if from_unit.quantity == Quantity::Temperature {
+        from_kelvin(to_kelvin(value, from_unit.names[0]), to_unit.names[0])
+    } else {
+        let from_factor = Decimal::from_str(from_unit.factor).unwrap();
+        let to_factor = Decimal::from_str(to_unit.factor).unwrap();
+        value
+            .checked_mul(from_factor)
+            .and_then(|x| x.checked_div(to_factor))
+    };
+
+    converted
+        .map(|x| x.round_dp(RESULT_DECIMALS).normalize())
+        .ok_or(String::from("The result is too large"))
+}
+
+fn find_unit(name: &str) -> Result<&'static Unit, String> {
+    let name = name.trim().to_lowercase();
+    let singular = name.strip_suffix('s').unwrap_or(&name);
+    UNITS
+        .iter()
+        .find(|unit| unit.names.contains(&name.as_str()))
+        .or_else(|| UNITS.iter().find(|unit| unit.names.contains(&singular)))
+        .ok_or(format!("Unknown unit {}", name))
+}
+
+fn to_kelvin(value: Decimal, unit: &str) -> Decimal {
+    let absolute_zero = Decimal::new(27315, 2);
+    match unit {
+        "c" => value + absolute_zero,
+        "f" => (value - Decimal::from(32)) * Decimal::from(5) / Decimal::from(9) + absolute_zero,
+        _ => value,
+    }
+}
+
+fn from_kelvin(kelvin: Decimal, unit: &str) -> Option<Decimal> {
+    let absolute_zero = Decimal::new(27315, 2);
+    match unit {
+        "c" => Some(kelvin - absolute_zero),
+        "f" => {
+            Some((kelvin - absolute_zero) * Decimal::from(9) / Decimal::from(5) + Decimal::from(32))
+        }
+        _ => Some(kelvin),
+    }
+}
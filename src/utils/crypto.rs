@@ -0,0 +1,62 @@
+// Encryption of secrets stored in the database
+use base64::prelude::*;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// AES-256-GCM key from the config, encrypting secrets such as the API keys of users.
+pub struct MasterKey {
+    key: LessSafeKey,
+}
+
+impl MasterKey {
+    /// Reads a base64 encoded 32 byte key, e.g. from `openssl rand -base64 32`.
+    pub fn from_base64(value: &str) -> Result<Self, String> {
+        let bytes = BASE64_STANDARD
+            .decode(value.trim())
+            .map_err(|err| format!("master key is not valid base64: {}", err))?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| format!("master key has {} bytes instead of 32", bytes.len()))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    /// Encrypts with a random nonce, the result is the base64 of the nonce followed by the ciphertext.
+    /// `context` is authenticated, decrypting only works with the same one, e.g. the owner's id.
+    pub fn encrypt(&self, plaintext: &str, context: &[u8]) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("system random generator failed");
+
+        let mut data = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(context),
+                &mut data,
+            )
+            .expect("encryption failed");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        BASE64_STANDARD.encode(sealed)
+    }
+
+    /// None when the value was changed, encrypted with another key or for another context.
+    pub fn decrypt(&self, sealed: &str, context: &[u8]) -> Option<String> {
+        let sealed = BASE64_STANDARD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+
+        let mut data = data.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(context), &mut data)
+            .ok()?;
+        String::from_utf8(plaintext.to_vec()).ok()
+    }
+}
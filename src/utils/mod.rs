@@ -1,2 +1,4 @@
+pub mod crypto;
+pub mod redact;
 pub mod string;
 pub mod time;
\ No newline at end of file
@@ -0,0 +1,140 @@
+// Keeps API keys, and unless configured otherwise message content, out of the logs
+use log::{Log, Metadata, Record};
+use std::sync::RwLock;
+
+pub const REDACTED: &str = "[redacted]";
+
+/// Prefix of Google API keys, followed by 35 more characters.
+const GOOGLE_KEY_PREFIX: &str = "AIza";
+const GOOGLE_KEY_LEN: usize = 39;
+
+/// Secrets known to the process, every one of them is replaced in log messages.
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Adds a secret to replace in everything logged from now on.
+pub fn register_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|x| x == secret) {
+        secrets.push(secret.to_string());
+        // Longest first, so a secret containing another one is replaced as a whole
+        secrets.sort_by_key(|x| std::cmp::Reverse(x.len()));
+    }
+}
+
+/// Replaces registered secrets, `key=` query parameters and anything that looks like a Google API key.
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.read().unwrap().iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+    redact_query_keys(&redact_google_keys(&text))
+}
+
+/// Message content as it may be logged: the text itself when `log_content` is set,
+/// otherwise only its length.
+pub fn content(text: &str, log_content: bool) -> String {
+    if log_content {
+        text.to_string()
+    } else {
+        format!("[{} chars]", text.chars().count())
+    }
+}
+
+fn redact_google_keys(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(GOOGLE_KEY_PREFIX) {
+        let candidate = &rest[start..];
+        let len = candidate
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(candidate.len());
+        result.push_str(&rest[..start]);
+        if len >= GOOGLE_KEY_LEN {
+            result.push_str(REDACTED);
+        } else {
+            result.push_str(&candidate[..len]);
+        }
+        rest = &candidate[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn redact_query_keys(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("key=") {
+        let preceded = rest[..start]
+            .chars()
+            .last()
+            .is_some_and(|c| c == '?' || c == '&');
+        let value = &rest[start + 4..];
+        let len = value
+            .find(|c: char| matches!(c, '&' | '#' | ' ' | ')' | '"' | '\'') || c.is_whitespace())
+            .unwrap_or(value.len());
+        result.push_str(&rest[..start + 4]);
+        if preceded && len > 0 {
+            result.push_str(REDACTED);
+        } else {
+            result.push_str(&value[..len]);
+        }
+        rest = &value[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Passes every record through `redact` before handing it to the actual logger.
+pub struct RedactingLogger {
+    inner: Box<dyn Log>,
+}
+
+impl RedactingLogger {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Log for RedactingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let message = redact(&record.args().to_string());
+        self.inner.log(
+            &Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("{}", message))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Installs pretty_env_logger, configured by `RUST_LOG`, behind a `RedactingLogger`.
+pub fn init_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let logger = builder.build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(RedactingLogger::new(Box::new(logger))))
+        .expect("a logger was already installed");
+    log::set_max_level(max_level);
+}